@@ -0,0 +1,110 @@
+//! derive macros for tulip-sled-util, re-exported by it behind the `derive` feature
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+/// derives `DbKey` from the fields named by `#[db_key(field = "...")]`. a single
+/// field is used as the key directly, while multiple fields, given by repeating
+/// `field`, are joined in order by `CompositeKey::separator`. each field must
+/// implement `KeySegment`
+#[proc_macro_derive(DbKey, attributes(db_key))]
+pub fn derive_db_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_db_key(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_db_key(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = key_fields(&input)?;
+    let struct_fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .filter_map(|field| field.ident.clone())
+                .collect::<Vec<_>>(),
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "DbKey can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "DbKey can only be derived for structs",
+            ))
+        }
+    };
+    for field in &fields {
+        if !struct_fields.contains(field) {
+            return Err(syn::Error::new(
+                field.span(),
+                format!("no field named `{}`", field),
+            ));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let segments = fields.iter().enumerate().map(|(idx, field)| {
+        let separator = (idx > 0).then(|| quote!(let key = key.separator();));
+        quote! {
+            #separator
+            let key = ::tulip_sled_util::types::KeySegment::append_to(&self.#field, key);
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::tulip_sled_util::types::DbKey for #name #ty_generics #where_clause {
+            fn key(&self) -> ::tulip_sled_util::__private::anyhow::Result<::std::vec::Vec<u8>> {
+                let key = ::tulip_sled_util::types::CompositeKey::new();
+                #(#segments)*
+                Ok(key.into_bytes())
+            }
+        }
+    })
+}
+
+/// returns the fields named by the `db_key` attributes, in order
+fn key_fields(input: &DeriveInput) -> syn::Result<Vec<Ident>> {
+    let mut fields = Vec::new();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("db_key"))
+    {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected #[db_key(field = \"...\")]",
+                ))
+            }
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(name_value))
+                    if name_value.path.is_ident("field") =>
+                {
+                    match name_value.lit {
+                        Lit::Str(lit) => fields.push(lit.parse()?),
+                        lit => return Err(syn::Error::new(lit.span(), "expected a string")),
+                    }
+                }
+                nested => return Err(syn::Error::new(nested.span(), "expected field = \"...\"")),
+            }
+        }
+    }
+    if fields.is_empty() {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "missing #[db_key(field = \"...\")] attribute",
+        ));
+    }
+    Ok(fields)
+}
@@ -0,0 +1,89 @@
+//! async wrappers around the database and its trees, which run each blocking
+//! sled call on a background thread. a thread is spawned per call, so these
+//! suit occasional calls from async code rather than hot loops
+
+pub use crate::blocking::Blocking;
+
+use crate::blocking::spawn_blocking;
+use crate::error::Result;
+use crate::types::{DbKey, DbTrees};
+use crate::{Database, DbBatch, DbTree};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sled::IVec;
+use std::sync::Arc;
+
+/// AsyncDatabase wraps a Database, offloading its blocking calls
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    db: Arc<Database>,
+}
+
+/// AsyncDbTree wraps a DbTree, offloading its blocking calls
+#[derive(Clone)]
+pub struct AsyncDbTree {
+    tree: Arc<DbTree>,
+}
+
+impl AsyncDatabase {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self { db }
+    }
+    /// opens the given database tree, see `Database::open_tree`
+    pub async fn open_tree(&self, tree: DbTrees<'static>) -> Result<AsyncDbTree> {
+        let db = self.db.clone();
+        let tree = spawn_blocking(move || db.open_tree(tree)).await?;
+        Ok(AsyncDbTree::new(tree))
+    }
+    /// flushes the database, resolving to the number of bytes flushed
+    pub async fn flush_async(&self) -> Result<usize> {
+        self.db.flush_async().await
+    }
+    /// returns the wrapped database
+    pub fn inner(&self) -> &Arc<Database> {
+        &self.db
+    }
+}
+
+impl AsyncDbTree {
+    pub fn new(tree: Arc<DbTree>) -> Self {
+        Self { tree }
+    }
+    /// returns the value stored under key
+    pub fn get<K>(&self, key: K) -> Blocking<sled::Result<Option<IVec>>>
+    where
+        K: AsRef<[u8]> + Send + 'static,
+    {
+        let tree = self.tree.clone();
+        spawn_blocking(move || tree.get(key))
+    }
+    /// deserializes the value stored under key, see `DbTree::get_deserialize`
+    pub fn get_deserialize<K, T>(&self, key: K) -> Blocking<Result<Option<T>>>
+    where
+        K: AsRef<[u8]> + Send + 'static,
+        T: BorshDeserialize + Send + 'static,
+    {
+        let tree = self.tree.clone();
+        spawn_blocking(move || tree.get_deserialize(key))
+    }
+    /// inserts value under the key derived from it, returning the previous value if any
+    pub fn insert<T>(&self, value: T) -> Blocking<Result<Option<IVec>>>
+    where
+        T: BorshSerialize + DbKey + Send + 'static,
+    {
+        let tree = self.tree.clone();
+        spawn_blocking(move || tree.insert(&value))
+    }
+    /// atomically applies batch to the tree
+    pub fn apply_batch(&self, mut batch: DbBatch) -> Blocking<Result<()>> {
+        let tree = self.tree.clone();
+        spawn_blocking(move || tree.apply_batch(&mut batch))
+    }
+    /// flushes the tree, resolving to the number of bytes flushed
+    pub async fn flush_async(&self) -> sled::Result<usize> {
+        self.tree.flush_async().await
+    }
+    /// returns the wrapped tree
+    pub fn inner(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+}
@@ -0,0 +1,96 @@
+//! portable single file export and import of every tree in a database.
+//!
+//! the format is a magic header followed by a sequence of tree records, each
+//! being the tree name followed by its entries. every record starts with a
+//! tag byte, and every byte string is prefixed by its little-endian u64 length:
+//!
+//! ```text
+//! MAGIC
+//! (TAG_TREE name (TAG_ENTRY key value)* TAG_END)*
+//! TAG_END
+//! ```
+
+use crate::error::{Result, SledUtilsError};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 8] = b"SLEDUTL1";
+const TAG_END: u8 = 0;
+const TAG_TREE: u8 = 1;
+const TAG_ENTRY: u8 = 2;
+
+/// writes every tree in db, including the default tree, to writer
+pub(crate) fn export<W: Write>(db: &sled::Db, mut writer: W) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    for name in db.tree_names() {
+        let tree = db.open_tree(&name)?;
+        writer.write_all(&[TAG_TREE])?;
+        write_bytes(&mut writer, &name)?;
+        for entry in tree.iter() {
+            let (key, value) = entry?;
+            writer.write_all(&[TAG_ENTRY])?;
+            write_bytes(&mut writer, &key)?;
+            write_bytes(&mut writer, &value)?;
+        }
+        writer.write_all(&[TAG_END])?;
+    }
+    writer.write_all(&[TAG_END])?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// restores the trees written by `export` into db, overwriting existing keys
+pub(crate) fn import<R: Read>(db: &sled::Db, mut reader: R) -> Result<()> {
+    let mut magic = [0_u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("missing export header"));
+    }
+    loop {
+        match read_tag(&mut reader)? {
+            TAG_END => return Ok(()),
+            TAG_TREE => {
+                let tree = db.open_tree(read_bytes(&mut reader)?)?;
+                loop {
+                    match read_tag(&mut reader)? {
+                        TAG_END => break,
+                        TAG_ENTRY => {
+                            let key = read_bytes(&mut reader)?;
+                            let value = read_bytes(&mut reader)?;
+                            tree.insert(key, value)?;
+                        }
+                        _ => return Err(invalid_data("unexpected tag in tree record")),
+                    }
+                }
+            }
+            _ => return Err(invalid_data("unexpected tag before tree record")),
+        }
+    }
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_tag<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut tag = [0_u8; 1];
+    reader.read_exact(&mut tag)?;
+    Ok(tag[0])
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut len = [0_u8; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    let mut bytes = Vec::new();
+    // read through take so a corrupt length can't trigger a huge allocation up front
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(invalid_data("unexpected end of export"));
+    }
+    Ok(bytes)
+}
+
+fn invalid_data(msg: &str) -> SledUtilsError {
+    SledUtilsError::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
@@ -0,0 +1,155 @@
+//! runtime agnostic offloading of blocking sled calls onto background threads.
+//!
+//! the results are handed back through a bounded channel whose receiving end
+//! can be polled from any executor, as sled itself doesn't depend on one
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    /// set once the sender is dropped, after which no more items arrive
+    closed: bool,
+    /// set once the receiver is dropped, telling the sender to stop
+    abandoned: bool,
+    waker: Option<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// notified whenever space is freed or the receiver is dropped
+    space: Condvar,
+}
+
+/// the blocking half of a channel, used from the background thread
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// the polled half of a channel, used from async code
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// returns a channel buffering at most capacity items
+pub(crate) fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            capacity: capacity.max(1),
+            closed: false,
+            abandoned: false,
+            waker: None,
+        }),
+        space: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// blocks until there is space for item, returning false without sending
+    /// it if the receiver was dropped
+    pub(crate) fn send(&self, item: T) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.queue.len() >= state.capacity && !state.abandoned {
+            state = self.shared.space.wait(state).unwrap();
+        }
+        if state.abandoned {
+            return false;
+        }
+        state.queue.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+    /// sends item without blocking, discarding the oldest buffered item when the
+    /// channel is full. returns false without sending it if the receiver was dropped
+    pub(crate) fn send_lossy(&self, item: T) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.abandoned {
+            return false;
+        }
+        if state.queue.len() >= state.capacity {
+            state.queue.pop_front();
+        }
+        state.queue.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// returns the next item, or None once the sender is dropped and every
+    /// item has been received
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(item) = state.queue.pop_front() {
+            self.shared.space.notify_one();
+            return Poll::Ready(Some(item));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().abandoned = true;
+        self.shared.space.notify_all();
+    }
+}
+
+/// Blocking is a future resolving to the result of a closure run on a
+/// background thread by `spawn_blocking`
+pub struct Blocking<R> {
+    receiver: Receiver<R>,
+}
+
+/// runs f on a new background thread, returning a future of its result
+pub(crate) fn spawn_blocking<F, R>(f: F) -> Blocking<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = channel(1);
+    std::thread::spawn(move || {
+        sender.send(f());
+    });
+    Blocking { receiver }
+}
+
+impl<R> Future for Blocking<R> {
+    type Output = R;
+
+    /// panics if the closure panicked
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|result| result.expect("blocking task panicked"))
+    }
+}
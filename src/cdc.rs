@@ -0,0 +1,43 @@
+//! change data capture, recording every insert and remove made through the
+//! tree wrappers of a database opened with `DbOpts::cdc` in a dedicated tree
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+/// the tree in which change records are stored, keyed by their big-endian sequence
+pub const CDC_TREE_ID: &str = "__cdc";
+
+/// the kind of change described by a CdcRecord
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CdcOp {
+    Insert,
+    Remove,
+}
+
+/// CdcRecord describes a single insert or remove made to a tree
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CdcRecord {
+    pub op: CdcOp,
+    /// the name of the tree which was changed
+    pub tree: Vec<u8>,
+    pub key: Vec<u8>,
+    /// the inserted value, or None for a remove
+    pub value: Option<Vec<u8>>,
+    /// milliseconds since the unix epoch at which the change was recorded
+    pub timestamp_ms: u64,
+}
+
+impl CdcRecord {
+    pub(crate) fn new(tree: &[u8], key: &[u8], value: Option<&[u8]>) -> Self {
+        Self {
+            op: if value.is_some() {
+                CdcOp::Insert
+            } else {
+                CdcOp::Remove
+            },
+            tree: tree.to_vec(),
+            key: key.to_vec(),
+            value: value.map(<[u8]>::to_vec),
+            timestamp_ms: crate::ttl::now_ms(),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! pluggable serialization formats for values stored in the database.
+//!
+//! since sled's compression is database wide, codecs are also the place to
+//! apply per tree compression, by compressing the bytes of an inner codec
+
+use crate::error::{Result, SledUtilsError};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+/// Codec converts values of type T to and from the bytes stored in sled,
+/// allowing types which don't implement borsh to be stored through the
+/// `*_with` variants of the tree methods
+pub trait Codec<T> {
+    /// serializes value into bytes
+    fn encode(value: &T) -> Result<Vec<u8>>;
+    /// deserializes a value from bytes
+    fn decode(bytes: &[u8]) -> Result<T>;
+}
+
+/// BorshCodec serializes values with borsh, and is the format used by the
+/// methods which don't take an explicit codec
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BorshCodec;
+
+impl<T: BorshSerialize + BorshDeserialize> Codec<T> for BorshCodec {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        borsh::to_vec(value).map_err(SledUtilsError::Serialize)
+    }
+    fn decode(bytes: &[u8]) -> Result<T> {
+        T::try_from_slice(bytes).map_err(SledUtilsError::Deserialize)
+    }
+}
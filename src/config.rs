@@ -1,26 +1,146 @@
+use crate::error::{Result, SledUtilsError};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// the smallest page cache accepted by `DbOpts::validate`, in bytes
+pub const MIN_CACHE_CAPACITY: u64 = 1024 * 1024;
+/// the page cache used when `mode` is `Fast` and `system_page_cache` is None,
+/// which matches sled's own default of 1GiB
+pub const DEFAULT_FAST_CACHE_CAPACITY: u64 = 1024 * 1024 * 1024;
+/// the range of compression factors accepted by sled
+pub const COMPRESSION_FACTOR_RANGE: std::ops::RangeInclusive<i32> = 1..=22;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DbOpts {
-    /// if Some, enable compression and set factor to this
+    /// if true, every write made through the tree wrappers is also recorded in
+    /// the `cdc::CDC_TREE_ID` tree within the same transaction
+    #[serde(default)]
+    pub cdc: bool,
+    /// if Some, enable compression and set factor to this. sled applies
+    /// compression to the whole database, it can't be enabled per tree, and
+    /// it requires sled to be built with its `compression` feature. to
+    /// compress the values of a single tree instead, store them through
+    /// `DbTree::insert_with` using a `Codec` which compresses its output
     pub compression_factor: Option<i32>,
     /// if true, print profile stats when database is dropped
     pub debug: bool,
+    /// if Some, flush to disk every this many milliseconds instead of sled's
+    /// default of 500, with `Some(0)` disabling periodic flushing entirely
+    #[serde(default)]
+    pub flush_every_ms: Option<u64>,
     pub mode: Option<DbMode>,
     pub path: String,
-    /// size of system page cache in bytes
+    /// if true, writes made through the database wrappers are rejected and
+    /// missing trees are not created. sled itself has no read only mode, so
+    /// the database is still opened with an exclusive lock on its directory
+    #[serde(default)]
+    pub read_only: bool,
+    /// if Some, take a snapshot after this many operations. this is passed
+    /// through to sled, which currently ignores the setting
+    #[serde(default)]
+    pub snapshot_after_ops: Option<u64>,
+    /// size of system page cache in bytes, which must be at least
+    /// `MIN_CACHE_CAPACITY`. when None and `mode` is `Fast`,
+    /// `DEFAULT_FAST_CACHE_CAPACITY` is used
     pub system_page_cache: Option<u64>,
+    /// if true, the database is deleted from disk once it is dropped, which
+    /// is mostly useful for tests
+    #[serde(default)]
+    pub temporary: bool,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DbMode {
     LowSpace,
+    #[default]
     Fast,
 }
 
-impl Default for DbMode {
-    fn default() -> Self {
-        Self::Fast
+impl DbOpts {
+    /// returns the default options with path replaced
+    pub fn from_path(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            ..Default::default()
+        }
+    }
+    /// reads the options from the environment variables `{prefix}_PATH`,
+    /// `{prefix}_MODE`, `{prefix}_CACHE`, `{prefix}_COMPRESSION_FACTOR`,
+    /// `{prefix}_FLUSH_EVERY_MS`, `{prefix}_CDC`, `{prefix}_DEBUG`,
+    /// `{prefix}_READ_ONLY` and `{prefix}_TEMPORARY`, falling back to the
+    /// default of each unset variable. mode accepts `fast` or `low_space` case
+    /// insensitively, and an error is returned for any variable which fails to
+    /// be parsed
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            path: env_var(prefix, "PATH")?.unwrap_or(defaults.path),
+            mode: env_var(prefix, "MODE")?.or(defaults.mode),
+            system_page_cache: env_var(prefix, "CACHE")?.or(defaults.system_page_cache),
+            compression_factor: env_var(prefix, "COMPRESSION_FACTOR")?
+                .or(defaults.compression_factor),
+            flush_every_ms: env_var(prefix, "FLUSH_EVERY_MS")?.or(defaults.flush_every_ms),
+            cdc: env_var(prefix, "CDC")?.unwrap_or(defaults.cdc),
+            debug: env_var(prefix, "DEBUG")?.unwrap_or(defaults.debug),
+            read_only: env_var(prefix, "READ_ONLY")?.unwrap_or(defaults.read_only),
+            temporary: env_var(prefix, "TEMPORARY")?.unwrap_or(defaults.temporary),
+            ..defaults
+        })
+    }
+    /// returns an error if any of the options hold a value which sled would
+    /// reject, or which would cripple the database such as a tiny page cache
+    pub fn validate(&self) -> Result<()> {
+        if let Some(cache) = self.system_page_cache {
+            if cache < MIN_CACHE_CAPACITY {
+                return Err(SledUtilsError::InvalidConfig(format!(
+                    "system_page_cache of {} bytes is below the minimum of {} bytes",
+                    cache, MIN_CACHE_CAPACITY
+                )));
+            }
+        }
+        if let Some(compression_factor) = self.compression_factor {
+            if !COMPRESSION_FACTOR_RANGE.contains(&compression_factor) {
+                return Err(SledUtilsError::InvalidConfig(format!(
+                    "compression_factor of {} is outside of {:?}",
+                    compression_factor, COMPRESSION_FACTOR_RANGE
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// parses the environment variable `{prefix}_{name}`, returning None if it is unset
+fn env_var<T>(prefix: &str, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let key = format!("{}_{}", prefix, name);
+    match std::env::var(&key) {
+        Ok(value) => value.parse().map(Some).map_err(|err| {
+            SledUtilsError::InvalidConfig(format!("failed to parse {}={:?}: {}", key, value, err))
+        }),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(SledUtilsError::InvalidConfig(format!("{}: {}", key, err))),
+    }
+}
+
+impl FromStr for DbMode {
+    type Err = SledUtilsError;
+
+    /// parses `fast` or `low_space` case insensitively
+    fn from_str(mode: &str) -> Result<Self> {
+        if mode.eq_ignore_ascii_case("fast") {
+            Ok(DbMode::Fast)
+        } else if mode.eq_ignore_ascii_case("low_space") {
+            Ok(DbMode::LowSpace)
+        } else {
+            Err(SledUtilsError::InvalidConfig(format!(
+                "unknown mode {:?}, expected fast or low_space",
+                mode
+            )))
+        }
     }
 }
 
@@ -37,8 +157,12 @@ impl From<&DbOpts> for sled::Config {
     fn from(opts: &DbOpts) -> Self {
         let mut sled_config = sled::Config::new();
         sled_config = sled_config.path(opts.path.clone());
-        if let Some(cache) = opts.system_page_cache.as_ref() {
-            sled_config = sled_config.cache_capacity(*cache);
+        match (opts.system_page_cache, opts.mode) {
+            (Some(cache), _) => sled_config = sled_config.cache_capacity(cache),
+            (None, Some(DbMode::Fast)) => {
+                sled_config = sled_config.cache_capacity(DEFAULT_FAST_CACHE_CAPACITY)
+            }
+            (None, _) => {}
         }
         if let Some(compression) = opts.compression_factor.as_ref() {
             sled_config = sled_config.use_compression(true);
@@ -50,6 +174,21 @@ impl From<&DbOpts> for sled::Config {
         if opts.debug {
             sled_config = sled_config.print_profile_on_drop(true);
         }
+        if let Some(flush_every_ms) = opts.flush_every_ms {
+            sled_config = sled_config.flush_every_ms(match flush_every_ms {
+                0 => None,
+                flush_every_ms => Some(flush_every_ms),
+            });
+        }
+        if let Some(snapshot_after_ops) = opts.snapshot_after_ops {
+            #[allow(deprecated)]
+            {
+                sled_config = sled_config.snapshot_after_ops(snapshot_after_ops);
+            }
+        }
+        if opts.temporary {
+            sled_config = sled_config.temporary(true);
+        }
         sled_config
     }
 }
@@ -58,10 +197,15 @@ impl Default for DbOpts {
     fn default() -> Self {
         Self {
             path: "test_infos.db".to_string(),
+            cdc: false,
             system_page_cache: None,
             compression_factor: None,
             mode: Default::default(),
             debug: false,
+            flush_every_ms: None,
+            read_only: false,
+            snapshot_after_ops: None,
+            temporary: false,
         }
     }
 }
@@ -0,0 +1,40 @@
+//! atomic i64 counters stored in a dedicated tree
+
+use crate::error::Result;
+use crate::{counter_merge, DbTree};
+use std::sync::Arc;
+
+/// the tree in which every counter is stored
+pub const COUNTERS_TREE_ID: &str = "__sled_utils_counters";
+
+/// Counters updates i64 counters through sled's merge operator, so increments
+/// made concurrently by any number of writers are never lost. counters start
+/// at 0, and are stored as borsh encoded i64's under their key
+#[derive(Clone)]
+pub struct Counters {
+    tree: Arc<DbTree>,
+}
+
+impl Counters {
+    pub(crate) fn new(tree: Arc<DbTree>) -> Self {
+        // merge operators aren't persisted, so it's set every time the tree is opened
+        tree.set_merge_operator(counter_merge);
+        Self { tree }
+    }
+    /// atomically adds delta to the counter, returning its new value
+    pub fn incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        self.tree.increment(key, delta)
+    }
+    /// atomically subtracts delta from the counter, returning its new value
+    pub fn decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        self.tree.increment(key, delta.wrapping_neg())
+    }
+    /// returns the value of the counter
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
+        Ok(self.tree.get_deserialize(key)?.unwrap_or_default())
+    }
+    /// removes the counter, returning its last value
+    pub fn reset<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
+        Ok(self.tree.remove_deserialize(key)?.unwrap_or_default())
+    }
+}
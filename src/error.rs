@@ -0,0 +1,105 @@
+//! error types returned by the database wrappers
+
+use sled::IVec;
+use std::fmt;
+
+/// result type used throughout the crate
+pub type Result<T, E = SledUtilsError> = std::result::Result<T, E>;
+
+/// SledUtilsError enumerates the failures which can be returned by
+/// the database wrappers, allowing callers to react to each kind.
+///
+/// borsh reports both serialization and deserialization failures as
+/// `std::io::Error`, so those are mapped to `Serialize` or `Deserialize`
+/// explicitly at the call site, while the `From` impl for `std::io::Error`
+/// is reserved for `Io`
+#[derive(Debug)]
+pub enum SledUtilsError {
+    /// a value failed to be serialized
+    Serialize(std::io::Error),
+    /// a stored value failed to be deserialized
+    Deserialize(std::io::Error),
+    /// a stored key failed to be deserialized
+    DeserializeKey(std::io::Error),
+    /// no value is stored under the given key
+    KeyNotFound(IVec),
+    /// an error returned by the underlying sled database
+    Sled(sled::Error),
+    /// an error returned while deriving a key through `DbKey::key`
+    Key(anyhow::Error),
+    /// the operation is not valid for the object it was invoked on
+    InvalidOperation(&'static str),
+    /// the database options failed to be validated
+    InvalidConfig(String),
+    /// the row stored under the key failed to be transformed while migrating a tree
+    Transform(IVec, Box<SledUtilsError>),
+    /// a write was attempted on a database opened read only
+    ReadOnly,
+    /// the unique key of a value already belongs to a value stored under another key
+    DuplicateKey(IVec),
+    /// an io error returned while reading or writing an export
+    Io(std::io::Error),
+}
+
+impl fmt::Display for SledUtilsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SledUtilsError::Serialize(err) => write!(f, "failed to serialize value: {}", err),
+            SledUtilsError::Deserialize(err) => write!(f, "failed to deserialize value: {}", err),
+            SledUtilsError::DeserializeKey(err) => write!(f, "failed to deserialize key: {}", err),
+            SledUtilsError::KeyNotFound(key) => write!(f, "value for key {:?} is None", key),
+            SledUtilsError::Sled(err) => write!(f, "sled error: {}", err),
+            SledUtilsError::Key(err) => write!(f, "failed to derive key: {:#}", err),
+            SledUtilsError::InvalidOperation(msg) => write!(f, "invalid operation: {}", msg),
+            SledUtilsError::InvalidConfig(msg) => write!(f, "invalid config: {}", msg),
+            SledUtilsError::Transform(key, err) => {
+                write!(f, "failed to transform value for key {:?}: {}", key, err)
+            }
+            SledUtilsError::ReadOnly => write!(f, "database was opened read only"),
+            SledUtilsError::DuplicateKey(key) => {
+                write!(f, "unique key {:?} is already in use", key)
+            }
+            SledUtilsError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SledUtilsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SledUtilsError::Serialize(err)
+            | SledUtilsError::Deserialize(err)
+            | SledUtilsError::DeserializeKey(err)
+            | SledUtilsError::Io(err) => Some(err),
+            SledUtilsError::Sled(err) => Some(err),
+            SledUtilsError::Key(err) => Some(err.as_ref()),
+            SledUtilsError::Transform(_, err) => Some(err.as_ref()),
+            SledUtilsError::KeyNotFound(_)
+            | SledUtilsError::InvalidOperation(_)
+            | SledUtilsError::InvalidConfig(_)
+            | SledUtilsError::ReadOnly
+            | SledUtilsError::DuplicateKey(_) => None,
+        }
+    }
+}
+
+impl From<sled::Error> for SledUtilsError {
+    fn from(err: sled::Error) -> Self {
+        SledUtilsError::Sled(err)
+    }
+}
+
+impl From<std::io::Error> for SledUtilsError {
+    fn from(err: std::io::Error) -> Self {
+        SledUtilsError::Io(err)
+    }
+}
+
+impl From<sled::transaction::TransactionError<SledUtilsError>> for SledUtilsError {
+    fn from(err: sled::transaction::TransactionError<SledUtilsError>) -> Self {
+        match err {
+            sled::transaction::TransactionError::Abort(err) => err,
+            sled::transaction::TransactionError::Storage(err) => SledUtilsError::Sled(err),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! an event bus broadcasting the typed changes made to a tree to any number of
+//! async consumers, fed by a background thread reading a sled subscriber
+
+use crate::blocking::{self, Receiver, Sender};
+use crate::watch::deserialize_event;
+use borsh::BorshDeserialize;
+use sled::{IVec, Subscriber};
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// the number of events buffered for each receiver, after which its oldest
+/// events are discarded
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+/// how often the forwarder checks whether its bus was dropped
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// an event as `TypedSubscriber` yields it
+type Change<T> = (IVec, Option<T>);
+
+struct Shared<T> {
+    senders: Mutex<Vec<Sender<Change<T>>>>,
+    stopped: AtomicBool,
+}
+
+/// EventBus forwards every change made to a tree to each of its receivers, as
+/// `(key, Some(value))` for an insert and `(key, None)` for a removal like
+/// `TypedSubscriber` does. inserted values which fail to be deserialized are
+/// logged and skipped. the forwarding thread stops once the bus is dropped, or
+/// the database is closed, after which receivers yield None once they're drained
+pub struct EventBus<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// EventReceiver receives the events of an EventBus. a receiver which falls
+/// more than `EVENT_BUS_CAPACITY` events behind misses the oldest of them
+pub struct EventReceiver<T> {
+    receiver: Receiver<Change<T>>,
+}
+
+impl<T: BorshDeserialize + Clone + Send + 'static> EventBus<T> {
+    pub(crate) fn new(mut subscriber: Subscriber) -> Self {
+        let shared = Arc::new(Shared {
+            senders: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        });
+        let forwarder = shared.clone();
+        std::thread::spawn(move || {
+            while !forwarder.stopped.load(Ordering::Acquire) {
+                let event = match subscriber.next_timeout(STOP_POLL_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                let event = match deserialize_event::<T>(event) {
+                    Ok(event) => event,
+                    Err(err) => {
+                        log::warn!("skipping undecodable insert: {:#?}", err);
+                        continue;
+                    }
+                };
+                forwarder
+                    .senders
+                    .lock()
+                    .unwrap()
+                    .retain(|sender| sender.send_lossy(event.clone()));
+            }
+            // dropping the senders lets the receivers finish
+            forwarder.senders.lock().unwrap().clear();
+        });
+        Self { shared }
+    }
+    /// returns a receiver of every event forwarded from now on
+    pub fn subscribe(&self) -> EventReceiver<T> {
+        let (sender, receiver) = blocking::channel(EVENT_BUS_CAPACITY);
+        self.shared.senders.lock().unwrap().push(sender);
+        EventReceiver { receiver }
+    }
+}
+
+impl<T> Drop for EventBus<T> {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Release);
+    }
+}
+
+impl<T> EventReceiver<T> {
+    /// polls for the next event, returning None once the bus has stopped
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<(IVec, Option<T>)>> {
+        self.receiver.poll_recv(cx)
+    }
+    /// resolves to the next event, or None once the bus has stopped
+    pub async fn recv(&mut self) -> Option<(IVec, Option<T>)> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+}
@@ -1,27 +1,104 @@
 //! an embedded database using the sled framework
 //!
 use borsh::{BorshDeserialize, BorshSerialize};
+// lets the code generated by sled-utils-derive refer to this crate from within it
+extern crate self as tulip_sled_util;
+pub mod asynchronous;
+mod backup;
+mod blocking;
+pub mod cdc;
+pub mod codec;
 pub mod config;
+pub mod counters;
+pub mod error;
+pub mod events;
+pub mod migrate;
+pub mod multimap;
+pub mod queue;
+pub mod sequence;
+pub mod sorted_set;
+pub mod stream;
+pub mod transaction;
+pub mod ttl;
+pub mod typed;
 pub mod types;
-use anyhow::{anyhow, Result};
+pub mod unique;
+pub mod versioned;
+pub mod watch;
+use cdc::{CdcRecord, CDC_TREE_ID};
+use codec::Codec;
 use config::DbOpts;
-use sled::{IVec, Tree};
-use std::sync::Arc;
+use counters::{Counters, COUNTERS_TREE_ID};
+use error::{Result, SledUtilsError};
+use events::EventBus;
+use migrate::Migrator;
+use multimap::MultiMap;
+use queue::{Deque, PriorityQueue, Queue};
+use sequence::{Sequence, SEQUENCES_TREE_ID};
+use sled::transaction::{
+    ConflictableTransactionResult, TransactionError, TransactionResult, TransactionalTree,
+};
+use sled::{IVec, Transactional, Tree};
+use sorted_set::SortedSet;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::iter::Rev;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use stream::TreeStream;
+use transaction::transact;
+use ttl::{Deadlines, ExpirationWorker};
+use typed::TypedTree;
+use unique::{UniqueIndex, UniqueKey};
+use versioned::{Versioned, VersionedCodec};
+use watch::{EventSubscriber, TypedSubscriber};
 
-use self::types::{DbKey, DbTrees};
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow;
+}
+
+use self::types::{DbKey, DbTrees, GeneratedKey, TimeKey, DEFAULT_TREE_ID};
 
 /// Database is the main embedded database object using the
 /// sled db
 #[derive(Clone)]
 pub struct Database {
     db: sled::Db,
+    /// if true, writes made through the wrappers are rejected
+    read_only: bool,
+    /// if true, writes made through the tree wrappers are recorded in the cdc tree
+    cdc: bool,
+    /// the deadlines of entries inserted with a ttl, shared with every opened tree
+    deadlines: Deadlines,
+    /// the merge operators set through the opened trees
+    merge_operators: MergeOperators,
 }
 
+/// a merge operator set through `DbTree::set_merge_operator`
+type SharedMergeOperator =
+    Arc<dyn Fn(&[u8], Option<&[u8]>, &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+/// the merge operators set through the trees of a database, by tree name, which
+/// merges apply themselves when they have to be written in a transaction
+type MergeOperators = Arc<RwLock<HashMap<IVec, SharedMergeOperator>>>;
+
 /// DbTree is a wrapper around the sled::Tree type providing
 /// convenience functions
 #[derive(Clone)]
 pub struct DbTree {
     pub tree: Tree,
+    /// the database the tree belongs to
+    db: sled::Db,
+    /// if true, writes made through the wrapper are rejected
+    read_only: bool,
+    /// if Some, the cdc tree in which writes made through the wrapper are recorded
+    cdc: Option<Tree>,
+    /// the deadlines consulted on reads so expired entries are never returned
+    deadlines: Deadlines,
+    /// the merge operators set through the trees of the database
+    merge_operators: MergeOperators,
 }
 
 /// DbBatch is a wrapper around the sled::Batch type providing
@@ -29,20 +106,316 @@ pub struct DbTree {
 #[derive(Default, Clone)]
 pub struct DbBatch {
     batch: sled::Batch,
-    count: u64,
+    /// mirrors the operations queued in batch, which sled doesn't expose, with
+    /// values shared with batch rather than copied
+    writes: BTreeMap<IVec, Option<IVec>>,
+    insert_count: u64,
+    remove_count: u64,
+    /// if Some, the batch is applied to the tree whenever count reaches the limit
+    auto_flush: Option<(Arc<DbTree>, u64)>,
+}
+
+/// VerifyReport summarizes the result of `DbTree::verify`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// the number of entries in the tree
+    pub total: u64,
+    /// the number of entries whose value was successfully deserialized
+    pub decoded: u64,
+    /// the keys of entries whose value failed to be deserialized
+    pub failed_keys: Vec<IVec>,
+}
+
+impl VerifyReport {
+    /// returns true if every entry was successfully deserialized
+    pub fn is_ok(&self) -> bool {
+        self.failed_keys.is_empty()
+    }
+}
+
+/// DbStats summarizes the contents of a database, as returned by `Database::stats`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DbStats {
+    /// the number of trees, excluding the internal default tree
+    pub tree_count: u64,
+    /// the number of entries summed across every tree, or None unless
+    /// returned by `Database::stats_with_entries`
+    pub total_entries: Option<u64>,
+    /// the size of the database on disk in bytes
+    pub size_on_disk: u64,
+}
+
+/// BatchApplyReport summarizes the result of `DbTree::apply_batch_checked`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatchApplyReport {
+    /// the number of keys whose stored value was changed by the batch
+    pub changed: u64,
+    /// the number of keys whose stored value already matched the batch
+    pub unchanged: u64,
+}
+
+/// ScanPage is a page of entries returned by `Database::scan_page`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanPage {
+    /// the entries of the page in key order
+    pub entries: Vec<(IVec, IVec)>,
+    /// the cursor to pass to `scan_page` for the next page, or None if the
+    /// tree has no entries after this page
+    pub cursor: Option<IVec>,
+}
+
+/// CasError is returned by `DbTree::compare_and_swap_typed` when the stored
+/// value didn't match the expected one, holding the deserialized current value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CasError<T> {
+    /// the value currently stored, or None if the key is absent
+    pub current: Option<T>,
+}
+
+/// TreeBatch is a DbBatch bound to the tree it was created from, so
+/// that it can only ever be applied to that tree
+#[derive(Clone)]
+pub struct TreeBatch {
+    tree: Arc<DbTree>,
+    batch: DbBatch,
 }
 
 impl Database {
     /// returns a new sled database
     pub fn new(cfg: &DbOpts) -> Result<Arc<Self>> {
+        cfg.validate()?;
         let sled_config: sled::Config = cfg.into();
         let db = sled_config.open()?;
         drop(sled_config);
-        Ok(Arc::new(Database { db }))
+        Ok(Arc::new(Database {
+            deadlines: Deadlines::load(&db)?,
+            db,
+            read_only: cfg.read_only,
+            cdc: cfg.cdc,
+            merge_operators: MergeOperators::default(),
+        }))
     }
-    /// opens the given database tree
+    /// returns a new sled database, after applying the pending migrations of
+    /// migrator to it. opening fails if any migration fails
+    pub fn new_with_migrator(cfg: &DbOpts, migrator: &Migrator) -> Result<Arc<Self>> {
+        let db = Database::new(cfg)?;
+        migrator.run(&db)?;
+        Ok(db)
+    }
+    /// opens a database from a fully user constructed sled config, bypassing
+    /// `DbOpts` so every sled setting can be reached. the database isn't read only
+    pub fn from_sled_config(config: sled::Config) -> Result<Arc<Self>> {
+        let db = config.open()?;
+        Ok(Arc::new(Database {
+            deadlines: Deadlines::load(&db)?,
+            db,
+            read_only: false,
+            cdc: false,
+            merge_operators: MergeOperators::default(),
+        }))
+    }
+    /// returns a new database stored under a unique path in the system temp
+    /// directory, which is removed once the database is dropped
+    pub fn new_temporary() -> Result<Arc<Self>> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let path = std::env::temp_dir().join(format!(
+            "sled-utils-{}-{}-{}",
+            std::process::id(),
+            nanos,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        Database::new(&DbOpts {
+            path: path.to_string_lossy().into_owned(),
+            temporary: true,
+            ..Default::default()
+        })
+    }
+    /// opens the given database tree. when the database is read only the tree
+    /// is not created if missing, and `sled::Error::CollectionNotFound` is returned
     pub fn open_tree(self: &Arc<Self>, tree: DbTrees) -> Result<Arc<DbTree>> {
-        DbTree::open(&self.db, tree)
+        Ok(Arc::new(self.open_db_tree(tree)?))
+    }
+    /// opens the given database tree as a TypedTree storing values of type V under keys of type K
+    pub fn open_typed_tree<K, V>(self: &Arc<Self>, tree: DbTrees) -> Result<TypedTree<K, V>>
+    where
+        K: AsRef<[u8]>,
+        V: BorshSerialize + BorshDeserialize,
+    {
+        Ok(TypedTree::new(self.open_tree(tree)?))
+    }
+    /// opens each of the given trees, returning them in the same order and
+    /// failing on the first tree which can't be opened
+    pub fn open_trees(self: &Arc<Self>, trees: &[DbTrees]) -> Result<Vec<Arc<DbTree>>> {
+        trees.iter().map(|tree| self.open_tree(*tree)).collect()
+    }
+    /// opens each of the given trees, returning them keyed by their name
+    pub fn open_trees_named(
+        self: &Arc<Self>,
+        trees: &[DbTrees],
+    ) -> Result<HashMap<String, Arc<DbTree>>> {
+        trees
+            .iter()
+            .map(|tree| Ok((tree.to_string(), self.open_tree(*tree)?)))
+            .collect()
+    }
+    /// opens the given tree as a Queue of values of type T
+    pub fn open_queue<T>(self: &Arc<Self>, tree: DbTrees) -> Result<Queue<T>>
+    where
+        T: BorshSerialize + BorshDeserialize,
+    {
+        Ok(Queue::new(self.open_tree(tree)?))
+    }
+    /// opens the given tree as a Deque of values of type T
+    pub fn open_deque<T>(self: &Arc<Self>, tree: DbTrees) -> Result<Deque<T>>
+    where
+        T: BorshSerialize + BorshDeserialize,
+    {
+        Ok(Deque::new(self.open_tree(tree)?))
+    }
+    /// opens the given tree as a PriorityQueue of values of type T
+    pub fn open_priority_queue<T>(self: &Arc<Self>, tree: DbTrees) -> Result<PriorityQueue<T>>
+    where
+        T: BorshSerialize + BorshDeserialize,
+    {
+        Ok(PriorityQueue::new(self.open_tree(tree)?))
+    }
+    /// returns a SortedSet storing the score of each member in the members tree,
+    /// indexed by score in the scores tree
+    pub fn open_sorted_set(
+        self: &Arc<Self>,
+        members: DbTrees,
+        scores: DbTrees,
+    ) -> Result<SortedSet> {
+        Ok(SortedSet::new(
+            self.open_tree(members)?,
+            self.open_tree(scores)?,
+        ))
+    }
+    /// opens the given tree as a MultiMap of values of type T
+    pub fn open_multimap<T>(self: &Arc<Self>, tree: DbTrees) -> Result<MultiMap<T>>
+    where
+        T: BorshSerialize + BorshDeserialize,
+    {
+        Ok(MultiMap::new(self.open_tree(tree)?))
+    }
+    /// returns the counters stored in the `COUNTERS_TREE_ID` tree
+    pub fn counters(self: &Arc<Self>) -> Result<Counters> {
+        Ok(Counters::new(
+            self.open_tree(DbTrees::Custom(COUNTERS_TREE_ID))?,
+        ))
+    }
+    /// returns a UniqueIndex storing values in the data tree, whose unique keys
+    /// are indexed in the index tree
+    pub fn unique_index<T>(
+        self: &Arc<Self>,
+        data: DbTrees,
+        index: DbTrees,
+    ) -> Result<UniqueIndex<T>>
+    where
+        T: BorshSerialize + BorshDeserialize + DbKey + UniqueKey,
+    {
+        Ok(UniqueIndex::new(
+            self.open_tree(data)?,
+            self.open_tree(index)?,
+        ))
+    }
+    /// returns the named sequence, which is stored in the `SEQUENCES_TREE_ID` tree
+    pub fn sequence(self: &Arc<Self>, name: &str) -> Result<Sequence> {
+        let tree = self.open_tree(DbTrees::Custom(SEQUENCES_TREE_ID))?;
+        Ok(Sequence::new(tree, name))
+    }
+    /// returns an event bus broadcasting every change made to the given tree,
+    /// deserializing inserted values, to any number of receivers
+    pub fn event_bus<T>(self: &Arc<Self>, tree: DbTrees) -> Result<EventBus<T>>
+    where
+        T: BorshDeserialize + Clone + Send + 'static,
+    {
+        Ok(EventBus::new(self.open_tree(tree)?.watch_prefix(vec![])))
+    }
+    /// returns at most limit change records whose sequence is after the given
+    /// one, or from the first record when it's None, in the order they were
+    /// recorded. sequences are increasing but not necessarily contiguous
+    pub fn cdc_tail(&self, after: Option<u64>, limit: usize) -> Result<Vec<(u64, CdcRecord)>> {
+        let cdc = self.db.open_tree(CDC_TREE_ID)?;
+        let iter = match after {
+            Some(seq) => cdc.range((Bound::Excluded(seq.to_be_bytes()), Bound::Unbounded)),
+            None => cdc.iter(),
+        };
+        iter.take(limit)
+            .map(|entry| {
+                let (key, record) = deserialize_entry::<CdcRecord>(entry)?;
+                let seq = key
+                    .as_ref()
+                    .try_into()
+                    .map(u64::from_be_bytes)
+                    .map_err(|_| {
+                        SledUtilsError::DeserializeKey(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "cdc sequence isn't a u64",
+                        ))
+                    })?;
+                Ok((seq, record))
+            })
+            .collect()
+    }
+    /// removes every change record whose sequence is up to and including the
+    /// given one, returning the number of records removed
+    pub fn cdc_truncate(&self, up_to: u64) -> Result<u64> {
+        self.ensure_writable()?;
+        let cdc = self.db.open_tree(CDC_TREE_ID)?;
+        let mut batch = sled::Batch::default();
+        let mut count = 0;
+        for key in cdc.range(..=up_to.to_be_bytes()).keys() {
+            batch.remove(key?);
+            count += 1;
+        }
+        cdc.apply_batch(batch)?;
+        Ok(count)
+    }
+    /// removes every entry inserted through `DbTree::insert_with_ttl` whose
+    /// deadline has passed, returning the number of entries removed
+    pub fn purge_expired(&self) -> Result<u64> {
+        self.ensure_writable()?;
+        ttl::purge(self)
+    }
+    /// spawns a background thread purging expired entries every interval, which
+    /// passes the number of entries removed, or the error returned, to on_purge.
+    /// the thread stops once the returned worker is dropped
+    pub fn spawn_expiration_worker<F>(
+        self: &Arc<Self>,
+        interval: Duration,
+        on_purge: F,
+    ) -> ExpirationWorker
+    where
+        F: FnMut(Result<u64>) + Send + 'static,
+    {
+        ExpirationWorker::spawn(self.clone(), interval, on_purge)
+    }
+    /// returns true if the given tree exists, without creating it
+    pub fn tree_exists(&self, tree: DbTrees) -> bool {
+        let name = tree.str();
+        self.db
+            .tree_names()
+            .iter()
+            .any(|tree_name| tree_name == name.as_bytes())
+    }
+    /// returns the names of every tree, excluding the internal default tree.
+    /// names which aren't valid utf8 are converted lossily
+    pub fn tree_names(&self) -> Vec<String> {
+        self.db
+            .tree_names()
+            .iter()
+            .filter(|tree_name| tree_name.as_ref() != DEFAULT_TREE_ID.as_bytes())
+            .map(|tree_name| String::from_utf8_lossy(tree_name).into_owned())
+            .collect()
+    }
+    /// returns true if the database was opened read only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
     /// opens the given db tree, return a vector of (key, value)
     pub fn list_values(self: &Arc<Self>, tree: DbTrees) -> Result<Vec<(IVec, IVec)>> {
@@ -58,16 +431,283 @@ impl Database {
             })
             .collect())
     }
+    /// opens the given db tree, return a vector of (key, value), invoking
+    /// on_error for each entry which fails to be read instead of discarding it
+    pub fn list_values_reporting(
+        self: &Arc<Self>,
+        tree: DbTrees,
+        mut on_error: impl FnMut(sled::Error),
+    ) -> Result<Vec<(IVec, IVec)>> {
+        let tree = self.open_tree(tree)?;
+        Ok(tree
+            .iter()
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    on_error(err);
+                    None
+                }
+            })
+            .collect())
+    }
+    /// opens the given db tree, returning a vector of its keys
+    pub fn list_keys(self: &Arc<Self>, tree: DbTrees) -> Result<Vec<IVec>> {
+        Ok(self.open_tree(tree)?.keys().collect::<sled::Result<_>>()?)
+    }
+    /// opens the given db tree, returning at most limit (key, value) pairs whose
+    /// key sorts after start_after, or from the first key when it's None. the last
+    /// key returned can be passed as start_after to fetch the next page
+    pub fn list_paginated(
+        self: &Arc<Self>,
+        tree: DbTrees,
+        start_after: Option<IVec>,
+        limit: usize,
+    ) -> Result<Vec<(IVec, IVec)>> {
+        let tree = self.open_tree(tree)?;
+        let iter = match start_after {
+            Some(key) => tree.range((Bound::Excluded(key), Bound::Unbounded)),
+            None => tree.iter(),
+        };
+        Ok(iter.take(limit).collect::<sled::Result<_>>()?)
+    }
+    /// opens the given db tree, returning a vector of (utf8 key, deserialized value)
+    pub fn list_string_keyed<T: BorshDeserialize>(
+        self: &Arc<Self>,
+        tree: DbTrees,
+    ) -> Result<Vec<(String, T)>> {
+        self.open_tree(tree)?
+            .iter_string_keys()
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((key, deserialize_value(&value)?))
+            })
+            .collect()
+    }
+    /// opens the given db tree, returning a page of at most limit entries whose
+    /// key sorts after cursor, or from the first key when it's None, along with
+    /// the cursor of the next page. limit must be greater than zero
+    pub fn scan_page(
+        self: &Arc<Self>,
+        tree: DbTrees,
+        cursor: Option<IVec>,
+        limit: usize,
+    ) -> Result<ScanPage> {
+        if limit == 0 {
+            return Err(SledUtilsError::InvalidOperation(
+                "scan_page limit must be greater than zero",
+            ));
+        }
+        let mut entries = self.list_paginated(tree, cursor.clone(), limit.saturating_add(1))?;
+        let has_more = entries.len() > limit;
+        entries.truncate(limit);
+        let cursor = if has_more {
+            entries.last().map(|(key, _)| key.clone()).or(cursor)
+        } else {
+            None
+        };
+        Ok(ScanPage { entries, cursor })
+    }
+    /// opens the given db tree, returning a vector of (key, deserialized value)
+    pub fn list_deserialized<T: BorshDeserialize>(
+        self: &Arc<Self>,
+        tree: DbTrees,
+    ) -> Result<Vec<(IVec, T)>> {
+        self.open_tree(tree)?.iter_deserialize().collect()
+    }
+    /// runs the closure as a single atomic transaction over the given trees,
+    /// which are handed to the closure in the same order they are specified.
+    /// returning `ConflictableTransactionError::Abort` from the closure aborts
+    /// the transaction, discarding writes made to every tree. the closure may be
+    /// invoked more than once if a conflict with a concurrent transaction is detected.
+    /// typed operations on the trees are provided by `TypedTransactionalTree`.
+    /// writes made through the raw trees aren't recorded in the cdc tree, and
+    /// leave the deadlines of their keys in place
+    pub fn transaction<F, A, E>(
+        self: &Arc<Self>,
+        trees: &[DbTrees],
+        f: F,
+    ) -> TransactionResult<A, E>
+    where
+        F: Fn(&[TransactionalTree]) -> ConflictableTransactionResult<A, E>,
+    {
+        if self.read_only {
+            return Err(TransactionError::Storage(sled::Error::Unsupported(
+                "database was opened read only".to_string(),
+            )));
+        }
+        let trees = trees
+            .iter()
+            .map(|tree| self.db.open_tree(tree.str().as_bytes()))
+            .collect::<sled::Result<Vec<Tree>>>()?;
+        trees.as_slice().transaction(|tx_trees| f(tx_trees))
+    }
+    /// atomically moves the entry stored under key from one tree to another,
+    /// overwriting any value stored under key in the destination tree. returns
+    /// false, leaving both trees untouched, if key is absent from the source tree
+    pub fn move_entry<K: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        from: DbTrees,
+        to: DbTrees,
+        key: K,
+    ) -> Result<bool> {
+        self.ensure_writable()?;
+        let key = key.as_ref();
+        if from.str() == to.str() {
+            return Ok(self.open_tree(from)?.contains_key(key)?);
+        }
+        let (from, to) = (self.open_db_tree(from)?, self.open_db_tree(to)?);
+        transact(&[&from, &to], |tx_trees| match tx_trees[0].get(key)? {
+            Some(value) => {
+                tx_trees[0].remove(key)?;
+                tx_trees[1].insert(key, value)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        })
+    }
+    /// removes every entry from the given tree while keeping it open
+    pub fn clear_tree(self: &Arc<Self>, tree: DbTrees) -> Result<()> {
+        self.open_tree(tree)?.clear()
+    }
+    /// reads every row of from, converts it with f and writes the result to to,
+    /// returning the number of rows migrated. the rows are written in a single
+    /// batch once every row has been converted, so to is left untouched if any
+    /// row fails to be deserialized or converted, which returns `Transform`
+    /// holding the offending key. from itself is never modified
+    pub fn transform_tree<T, U, F>(
+        self: &Arc<Self>,
+        from: DbTrees,
+        to: DbTrees,
+        f: F,
+    ) -> Result<u64>
+    where
+        T: BorshDeserialize,
+        U: BorshSerialize + DbKey,
+        F: Fn(T) -> Result<U>,
+    {
+        let from = self.open_tree(from)?;
+        let to = self.open_tree(to)?;
+        let mut batch = DbBatch::new();
+        for entry in from.iter() {
+            let (key, value) = entry?;
+            deserialize_value(&value)
+                .and_then(&f)
+                .and_then(|value| batch.insert(&value))
+                .map_err(|err| SledUtilsError::Transform(key, Box::new(err)))?;
+        }
+        let count = batch.count();
+        to.apply_batch(&mut batch)?;
+        Ok(count)
+    }
+    /// writes every tree, including the default tree, and all of their entries
+    /// to writer in a portable format which can be restored with `import`
+    pub fn export<W: Write>(self: &Arc<Self>, writer: W) -> Result<()> {
+        backup::export(&self.db, writer)
+    }
+    /// restores trees written by `export`, creating missing trees and
+    /// overwriting the values of existing keys
+    pub fn import<R: Read>(self: &Arc<Self>, reader: R) -> Result<()> {
+        if self.read_only {
+            return Err(SledUtilsError::ReadOnly);
+        }
+        backup::import(&self.db, reader)
+    }
+    /// returns a unique id which is greater than any id previously returned,
+    /// including across restarts
+    pub fn generate_id(&self) -> Result<u64> {
+        Ok(self.db.generate_id()?)
+    }
+    /// returns the size in bytes of the database's storage files. sled stores
+    /// every tree in the same files and keeps no size accounting per tree, so
+    /// there's no cheap equivalent for a single tree
+    pub fn size_on_disk(self: &Arc<Self>) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+    /// returns statistics about the database, without counting the entries of
+    /// its trees, so `total_entries` is None
+    pub fn stats(self: &Arc<Self>) -> Result<DbStats> {
+        self.collect_stats(false)
+    }
+    /// returns statistics about the database, including the number of entries
+    /// of every tree. counting them is a full scan of the database and
+    /// O(total entries), so it should be avoided on hot paths
+    pub fn stats_with_entries(self: &Arc<Self>) -> Result<DbStats> {
+        self.collect_stats(true)
+    }
+    fn collect_stats(&self, count_entries: bool) -> Result<DbStats> {
+        let mut tree_count = 0;
+        let mut total_entries = 0;
+        for name in self.db.tree_names() {
+            if name.as_ref() == DEFAULT_TREE_ID.as_bytes() {
+                continue;
+            }
+            tree_count += 1;
+            if count_entries {
+                total_entries += self.db.open_tree(name)?.len() as u64;
+            }
+        }
+        Ok(DbStats {
+            tree_count,
+            total_entries: count_entries.then_some(total_entries),
+            size_on_disk: self.db.size_on_disk()?,
+        })
+    }
+    /// flushes every tree and then the database itself, returning the total number
+    /// of bytes flushed, and consumes the handle so it can't be used afterwards.
+    /// the database is only closed once every other clone of the handle, and
+    /// every `DbTree` opened from it, has been dropped as well, and stays open
+    /// until then
+    pub fn shutdown(self: Arc<Self>) -> Result<usize> {
+        let mut flushed = 0;
+        for name in self.db.tree_names() {
+            flushed += self.db.open_tree(name)?.flush()?;
+        }
+        flushed += self.db.flush()?;
+        Ok(flushed)
+    }
     /// flushes teh database
     pub fn flush(self: &Arc<Self>) -> Result<usize> {
         Ok(self.db.flush()?)
     }
-    /// returns a clone of the inner database
+    /// asynchronously flushes the database, resolving to the number of bytes flushed
+    pub async fn flush_async(&self) -> Result<usize> {
+        Ok(self.db.flush_async().await?)
+    }
+    /// returns a clone of the inner database, writes made through it directly
+    /// bypass the read only check
     pub fn inner(self: &Arc<Self>) -> sled::Db {
         self.db.clone()
     }
+    /// drops the given tree and all of its entries, returning false if the tree
+    /// didn't exist. following sled's semantics, handles to the tree which are
+    /// still held remain usable, but they no longer refer to the named tree,
+    /// so writes made through them aren't visible when the tree is reopened
+    pub fn drop_tree(&self, tree: DbTrees) -> Result<bool> {
+        self.ensure_writable()?;
+        let name = tree.str();
+        let name = name.as_bytes();
+        if ttl::is_ttl_tree(name) {
+            let dropped = self.db.drop_tree(name)?;
+            self.deadlines.reset();
+            return Ok(dropped);
+        }
+        if self.tree_exists(tree) {
+            // clearing the tree first records its removals and drops its deadlines
+            let tree = self.open_db_tree(tree)?;
+            if tree.is_tracked() {
+                tree.clear()?;
+            }
+        }
+        let dropped = self.db.drop_tree(name)?;
+        self.deadlines.release(name);
+        Ok(dropped)
+    }
     /// destroys all trees except the default tree
     pub fn destroy(self: &Arc<Self>) {
+        if self.read_only {
+            log::error!("refusing to destroy a read only database");
+            return;
+        }
         const SLED_DEFAULT_TREE: &[u8] = b"__sled__default";
         self.db
             .tree_names()
@@ -78,45 +718,218 @@ impl Database {
                     log::error!("failed to drop tree {:?}: {:#?}", tree_name.as_ref(), err);
                 }
             });
+        // the deadlines were dropped along with every other tree
+        self.deadlines.reset();
     }
+    /// returns the value stored under key in the default tree, or None if it has expired
     pub fn get<K: AsRef<[u8]>>(&self, key: K) -> sled::Result<Option<sled::IVec>> {
-        self.db.get(key)
+        match self.db.get(&key)? {
+            Some(_) if self.deadlines.is_expired(&self.db, key.as_ref())? => Ok(None),
+            value => Ok(value),
+        }
     }
-    pub fn deserialize<K: AsRef<[u8]>, T>(&self, key: K) -> Result<T>
+    /// deserializes the value stored under key, returning `Ok(None)` if the key is
+    /// absent and only erroring when the value fails to be deserialized
+    pub fn get_deserialize<K: AsRef<[u8]>, T>(&self, key: K) -> Result<Option<T>>
     where
         T: BorshDeserialize,
     {
-        let value = self.get(key)?;
-        if let Some(value) = value {
-            Ok(borsh::de::BorshDeserialize::try_from_slice(&value)?)
-        } else {
-            Err(anyhow!("value for key is None"))
+        match self.get(key)? {
+            Some(value) => Ok(Some(deserialize_value(&value)?)),
+            None => Ok(None),
         }
     }
-    pub fn apply_batch(&self, batch: &mut DbBatch) -> sled::Result<()> {
-        self.db.apply_batch(batch.take_inner())
+    /// deserializes the value stored under key, returning an error if the key is absent
+    pub fn deserialize<K: AsRef<[u8]>, T>(&self, key: K) -> Result<T>
+    where
+        T: BorshDeserialize,
+    {
+        let key = key.as_ref();
+        self.get_deserialize(key)?
+            .ok_or_else(|| SledUtilsError::KeyNotFound(key.into()))
+    }
+    pub fn apply_batch(&self, batch: &mut DbBatch) -> Result<()> {
+        self.open_db_tree(DbTrees::Default)?.apply_batch(batch)
+    }
+    /// subscribes to changes of keys in the default tree starting with prefix,
+    /// an empty prefix subscribes to every key
+    pub fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> sled::Subscriber {
+        self.db.watch_prefix(prefix)
+    }
+    /// subscribes to changes of keys in the default tree starting with prefix,
+    /// deserializing the value of each insert
+    pub fn watch_prefix_deserialize<P: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        prefix: P,
+    ) -> TypedSubscriber<T> {
+        TypedSubscriber::new(self.db.watch_prefix(prefix))
     }
     /// inserts a value into the default tree
     pub fn insert<T>(&mut self, value: &T) -> Result<()>
     where
         T: BorshSerialize + DbKey,
     {
-        self.db.insert(
-            value.key()?,
-            match borsh::to_vec(value) {
-                Ok(data) => data,
-                Err(err) => return Err(anyhow!("failed to insert entry into batch {:#?}", err)),
-            },
-        )?;
+        self.open_db_tree(DbTrees::Default)?.insert(value)?;
         Ok(())
     }
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(SledUtilsError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+    /// opens the given tree, sharing the database's deadlines and recording its
+    /// writes in the cdc tree if enabled
+    fn open_db_tree(&self, tree: DbTrees) -> Result<DbTree> {
+        self.open_named(&tree.str())
+    }
+    /// opens the tree stored under name, as returned by `DbTrees::str`, like
+    /// `open_db_tree`
+    pub(crate) fn open_named(&self, name: &str) -> Result<DbTree> {
+        let mut tree = DbTree::open_with(
+            &self.db,
+            name,
+            self.read_only,
+            self.deadlines.clone(),
+            self.merge_operators.clone(),
+        )?;
+        if self.cdc && !self.read_only && tree.tree.name() != CDC_TREE_ID.as_bytes() {
+            tree.cdc = Some(self.db.open_tree(CDC_TREE_ID)?);
+        }
+        Ok(tree)
+    }
 }
 
 impl DbTree {
     pub fn open(db: &sled::Db, tree: DbTrees) -> Result<Arc<Self>> {
-        let tree = db.open_tree(tree.str())?;
-        Ok(Arc::new(Self { tree }))
+        Ok(Arc::new(Self::open_with(
+            db,
+            &tree.str(),
+            false,
+            Deadlines::load(db)?,
+            MergeOperators::default(),
+        )?))
+    }
+    /// opens an existing tree which rejects writes, without creating it if missing
+    pub fn open_read_only(db: &sled::Db, tree: DbTrees) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self::open_with(
+            db,
+            &tree.str(),
+            true,
+            Deadlines::load(db)?,
+            MergeOperators::default(),
+        )?))
+    }
+    fn open_with(
+        db: &sled::Db,
+        name: &str,
+        read_only: bool,
+        deadlines: Deadlines,
+        merge_operators: MergeOperators,
+    ) -> Result<Self> {
+        let name = name.as_bytes();
+        if read_only && !db.tree_names().iter().any(|tree_name| tree_name == name) {
+            return Err(sled::Error::CollectionNotFound(name.into()).into());
+        }
+        Ok(Self {
+            tree: db.open_tree(name)?,
+            db: db.clone(),
+            read_only,
+            cdc: None,
+            // the trees holding deadlines can't give their own entries one
+            deadlines: if ttl::is_ttl_tree(name) {
+                Deadlines::default()
+            } else {
+                deadlines
+            },
+            merge_operators,
+        })
+    }
+    /// returns true if writes made through this wrapper are rejected
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(SledUtilsError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+    /// returns true if writes must also be recorded in the cdc tree, or clear
+    /// the deadlines of their keys, which requires writing in a transaction.
+    /// a tree only clears deadlines once any of its keys was given one
+    fn is_tracked(&self) -> bool {
+        self.cdc.is_some() || self.deadlines.holds(&self.tree.name())
+    }
+    /// inserts value under key, or removes key when value is None, recording the
+    /// change in the cdc tree if enabled and clearing any deadline of the key.
+    /// returns the previous value
+    fn write(&self, key: IVec, value: Option<IVec>) -> Result<Option<IVec>> {
+        if !self.is_tracked() {
+            return Ok(match value {
+                Some(value) => self.tree.insert(key, value)?,
+                None => self.tree.remove(key)?,
+            });
+        }
+        transact(&[self], |tx_trees| match &value {
+            Some(value) => tx_trees[0].insert(&key, value.clone()),
+            None => tx_trees[0].remove(&key),
+        })
+    }
+    /// atomically applies batch, recording each of its changes in the cdc tree
+    /// if enabled and clearing the deadlines of its keys. a batch none of whose
+    /// keys has a deadline is applied directly unless writes are recorded
+    fn write_batch(&self, batch: &mut DbBatch) -> Result<()> {
+        if self.cdc.is_none()
+            && !self
+                .deadlines
+                .holds_any_of(&self.tree, batch.writes.keys())?
+        {
+            return Ok(self.tree.apply_batch(batch.take_inner())?);
+        }
+        let writes = std::mem::take(&mut batch.writes);
+        batch.take_inner();
+        transact(&[self], |tx_trees| {
+            for (key, value) in &writes {
+                match value {
+                    Some(value) => tx_trees[0].insert(key, value.clone())?,
+                    None => tx_trees[0].remove(key)?,
+                };
+            }
+            Ok(())
+        })
+    }
+    /// swaps the value stored under key from old to new, like sled's
+    /// `compare_and_swap`, recording the write in the cdc tree if enabled and
+    /// clearing any deadline of the key
+    pub(crate) fn cas(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<IVec>,
+    ) -> Result<std::result::Result<(), sled::CompareAndSwapError>> {
+        if !self.is_tracked() {
+            return Ok(self.tree.compare_and_swap(key, old, new)?);
+        }
+        transact(&[self], |tx_trees| {
+            let current = tx_trees[0].get(key)?;
+            if current.as_deref() != old {
+                return Ok(Err(sled::CompareAndSwapError {
+                    current,
+                    proposed: new.clone(),
+                }));
+            }
+            match &new {
+                Some(new) => tx_trees[0].insert(key, new.clone())?,
+                None => tx_trees[0].remove(key)?,
+            };
+            Ok(Ok(()))
+        })
+    }
+    /// returns the number of entries in the tree. this performs a full scan of
+    /// the tree, so avoid calling it in hot paths, `is_empty` is cheap however
     pub fn len(&self) -> usize {
         self.tree.len()
     }
@@ -126,84 +939,933 @@ impl DbTree {
     pub fn iter(&self) -> sled::Iter {
         self.tree.iter()
     }
+    /// returns an iterator over every key in the tree. values are dropped as
+    /// soon as they are yielded by sled rather than copied or deserialized
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = sled::Result<IVec>> {
+        self.tree.iter().keys()
+    }
+    /// returns an iterator over every entry in descending key order
+    pub fn iter_rev(&self) -> Rev<sled::Iter> {
+        self.tree.iter().rev()
+    }
+    /// returns an iterator which lazily deserializes each value in the tree,
+    /// yielding an error for entries which fail to be read or deserialized
+    pub fn iter_deserialize<T: BorshDeserialize>(&self) -> impl Iterator<Item = Result<(IVec, T)>> {
+        self.tree.iter().map(deserialize_entry)
+    }
+    /// returns a stream of the deserialized entries of the tree, which are read
+    /// on a background thread so that large scans don't block the executor
+    pub fn stream<T: BorshDeserialize + Send + 'static>(&self) -> TreeStream<T> {
+        TreeStream::new(self.tree.iter())
+    }
+    /// returns an iterator which lazily deserializes both the key and value of
+    /// each entry. keys which fail to be deserialized yield `DeserializeKey`,
+    /// keeping them distinct from `Deserialize` for values. trees whose keys
+    /// aren't borsh encoded should use `iter` or `iter_deserialize` instead
+    pub fn iter_typed<K: BorshDeserialize, V: BorshDeserialize>(
+        &self,
+    ) -> impl Iterator<Item = Result<(K, V)>> {
+        self.tree.iter().map(deserialize_typed_entry)
+    }
+    /// scans the tree in key order, returning the first entry whose deserialized
+    /// value matches pred. a value which fails to be deserialized aborts the
+    /// scan with an error rather than being skipped
+    pub fn find<T, F>(&self, pred: F) -> Result<Option<(IVec, T)>>
+    where
+        T: BorshDeserialize,
+        F: Fn(&T) -> bool,
+    {
+        for entry in self.tree.iter() {
+            let (key, value) = deserialize_entry(entry)?;
+            if pred(&value) {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+    /// returns an iterator which decodes the key of each entry as utf8, yielding
+    /// `DeserializeKey` for keys which aren't valid utf8
+    pub fn iter_string_keys(&self) -> impl Iterator<Item = Result<(String, IVec)>> {
+        self.tree.iter().map(|entry| {
+            let (key, value) = entry?;
+            Ok((decode_string_key(&key)?, value))
+        })
+    }
+    /// returns an iterator over all entries whose key starts with prefix
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> sled::Iter {
+        self.tree.scan_prefix(prefix)
+    }
+    /// returns an iterator which lazily deserializes each value whose key starts with prefix
+    pub fn scan_prefix_deserialize<P: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        prefix: P,
+    ) -> impl Iterator<Item = Result<(IVec, T)>> {
+        self.tree.scan_prefix(prefix).map(deserialize_entry)
+    }
+    /// returns an iterator over all entries whose key falls within range
+    pub fn range<K, R>(&self, range: R) -> sled::Iter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.tree.range(range)
+    }
+    /// returns an iterator over all entries whose key falls within range, in
+    /// descending key order
+    pub fn range_rev<K, R>(&self, range: R) -> Rev<sled::Iter>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.tree.range(range).rev()
+    }
+    /// returns an iterator which lazily deserializes each value whose key falls within range
+    pub fn range_deserialize<K, R, T>(&self, range: R) -> impl Iterator<Item = Result<(IVec, T)>>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+        T: BorshDeserialize,
+    {
+        self.tree.range(range).map(deserialize_entry)
+    }
+    /// returns an iterator which lazily deserializes each value whose `TimeKey`
+    /// has a timestamp from from_ms up to but excluding to_ms, in time order
+    pub fn range_by_time<T: BorshDeserialize>(
+        &self,
+        from_ms: u64,
+        to_ms: u64,
+    ) -> impl Iterator<Item = Result<(TimeKey, T)>> {
+        self.tree
+            .range(from_ms.to_be_bytes()..to_ms.max(from_ms).to_be_bytes())
+            .map(|entry| {
+                let (key, value) = deserialize_entry(entry)?;
+                Ok((TimeKey::parse(&key)?, value))
+            })
+    }
+    /// attempts to deserialize every value in the tree as T, reporting the keys
+    /// of all entries which fail rather than stopping at the first one. errors
+    /// returned by sled while reading the tree are returned immediately
+    pub fn verify<T: BorshDeserialize>(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        for entry in self.tree.iter() {
+            let (key, value) = entry?;
+            report.total += 1;
+            match deserialize_value::<T>(&value) {
+                Ok(_) => report.decoded += 1,
+                Err(_) => report.failed_keys.push(key),
+            }
+        }
+        Ok(report)
+    }
+    /// returns a batch bound to this tree, which is applied with `TreeBatch::commit`
+    pub fn batch(self: &Arc<Self>) -> TreeBatch {
+        TreeBatch {
+            tree: self.clone(),
+            batch: DbBatch::new(),
+        }
+    }
+    /// returns the entry with the smallest key which hasn't expired, or None if
+    /// there is none
+    pub fn first(&self) -> sled::Result<Option<(IVec, IVec)>> {
+        self.end(Iterator::next)
+    }
+    /// returns the entry with the largest key which hasn't expired, or None if
+    /// there is none
+    pub fn last(&self) -> sled::Result<Option<(IVec, IVec)>> {
+        self.end(DoubleEndedIterator::next_back)
+    }
+    /// returns the entry with the smallest key, deserializing its value
+    pub fn first_deserialize<T: BorshDeserialize>(&self) -> Result<Option<(IVec, T)>> {
+        self.first().transpose().map(deserialize_entry).transpose()
+    }
+    /// returns the entry with the largest key, deserializing its value
+    pub fn last_deserialize<T: BorshDeserialize>(&self) -> Result<Option<(IVec, T)>> {
+        self.last().transpose().map(deserialize_entry).transpose()
+    }
+    /// returns the first entry next takes from the entries of the tree which
+    /// hasn't expired
+    fn end<F>(&self, next: F) -> sled::Result<Option<(IVec, IVec)>>
+    where
+        F: Fn(&mut sled::Iter) -> Option<sled::Result<(IVec, IVec)>>,
+    {
+        let mut entries = self.tree.iter();
+        while let Some((key, value)) = next(&mut entries).transpose()? {
+            if !self.deadlines.is_expired(&self.tree, &key)? {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+    /// returns true if a value is stored under key, and it hasn't expired
     pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> sled::Result<bool> {
-        self.tree.contains_key(key)
+        Ok(
+            self.tree.contains_key(&key)?
+                && !self.deadlines.is_expired(&self.tree, key.as_ref())?,
+        )
     }
     pub fn flush(&self) -> sled::Result<usize> {
         self.tree.flush()
     }
-    pub fn apply_batch(&self, batch: &mut DbBatch) -> sled::Result<()> {
-        self.tree.apply_batch(batch.take_inner())
+    /// asynchronously flushes the tree, resolving to the number of bytes flushed
+    pub async fn flush_async(&self) -> sled::Result<usize> {
+        self.tree.flush_async().await
+    }
+    pub fn apply_batch(&self, batch: &mut DbBatch) -> Result<()> {
+        self.ensure_writable()?;
+        self.write_batch(batch)
+    }
+    /// applies the batch after comparing each of its operations with the value
+    /// currently stored, reporting how many keys were changed and how many were
+    /// left as they were, which allows replaying batches idempotently. keys
+    /// queued more than once are only compared by their final operation. this
+    /// reads every key in the batch, so it's more expensive than `apply_batch`,
+    /// and the comparison isn't atomic with the write, so the report may be
+    /// stale if the tree is written concurrently
+    pub fn apply_batch_checked(&self, batch: &mut DbBatch) -> Result<BatchApplyReport> {
+        self.ensure_writable()?;
+        let mut report = BatchApplyReport::default();
+        for (key, value) in &batch.writes {
+            if self.get(key)? == *value {
+                report.unchanged += 1;
+            } else {
+                report.changed += 1;
+            }
+        }
+        self.apply_batch(batch)?;
+        Ok(report)
+    }
+    /// applies the batch and then flushes the tree, so the batch is durable once
+    /// this returns. returns the number of bytes flushed
+    pub fn apply_batch_durable(&self, batch: &mut DbBatch) -> Result<usize> {
+        self.apply_batch(batch)?;
+        Ok(self.flush()?)
     }
     pub fn insert<T>(&self, value: &T) -> Result<Option<sled::IVec>>
     where
         T: BorshSerialize + DbKey,
     {
-        Ok(self.tree.insert(
-            value.key()?,
-            match borsh::to_vec(value) {
-                Ok(data) => data,
-                Err(err) => return Err(anyhow!("failed to insert entry {:#?}", err)),
-            },
-        )?)
+        self.ensure_writable()?;
+        self.write(
+            derive_key(value)?.into(),
+            Some(serialize_value(value)?.into()),
+        )
     }
-    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> sled::Result<Option<sled::IVec>> {
-        self.tree.get(key)
+    /// inserts value, which reads treat as absent once ttl elapses, and which is
+    /// removed by `Database::purge_expired` from then on. inserting the key with
+    /// a ttl again replaces its deadline, while any other write to the key
+    /// clears it
+    pub fn insert_with_ttl<T>(&self, value: &T, ttl: Duration) -> Result<Option<sled::IVec>>
+    where
+        T: BorshSerialize + DbKey,
+    {
+        self.ensure_writable()?;
+        let key = derive_key(value)?;
+        let value = IVec::from(serialize_value(value)?);
+        self.deadlines.open_for(&self.db, self.tree.name())?;
+        let deadline = ttl::deadline_after(ttl);
+        transact(&[self], |tx_trees| {
+            tx_trees[0].insert_with_deadline(&key, value.clone(), deadline)
+        })
     }
-    pub fn deserialize<K: AsRef<[u8]>, T>(&self, key: K) -> Result<T>
+    /// stores value under a newly generated id, returning the id. ids are
+    /// encoded big-endian so that iterating the tree yields values in the
+    /// order they were pushed
+    pub fn push<T: BorshSerialize>(&self, value: &T) -> Result<u64> {
+        self.ensure_writable()?;
+        let id = self.db.generate_id()?;
+        self.write(
+            IVec::from(&id.to_be_bytes()),
+            Some(serialize_value(value)?.into()),
+        )?;
+        Ok(id)
+    }
+    /// stores value under a newly generated UUIDv7, returning the key. unlike
+    /// `push`, keys don't depend on the database, so they stay unique when
+    /// entries are copied between databases
+    pub fn insert_auto_key<T: BorshSerialize>(&self, value: &T) -> Result<GeneratedKey> {
+        self.ensure_writable()?;
+        let key = GeneratedKey::generate();
+        self.write(
+            IVec::from(&key.to_bytes()),
+            Some(serialize_value(value)?.into()),
+        )?;
+        Ok(key)
+    }
+    /// atomically inserts every value in a single batch, returning the number
+    /// of values inserted. nothing is written if any value fails to serialize
+    pub fn insert_all<T, I>(&self, values: I) -> Result<u64>
     where
-        T: BorshDeserialize,
+        T: BorshSerialize + DbKey,
+        I: IntoIterator<Item = T>,
     {
-        let value = self.get(key)?;
-        if let Some(value) = value {
-            Ok(borsh::de::BorshDeserialize::try_from_slice(&value)?)
-        } else {
-            Err(anyhow!("value for key is None"))
+        self.ensure_writable()?;
+        let mut batch = DbBatch::new();
+        for value in values {
+            batch.insert(&value)?;
         }
+        let count = batch.count();
+        self.apply_batch(&mut batch)?;
+        Ok(count)
     }
-}
-
-impl DbBatch {
-    pub fn new() -> DbBatch {
-        DbBatch {
-            batch: Default::default(),
-            count: 0,
+    /// inserts a value serialized with the codec C rather than borsh
+    pub fn insert_with<C, T>(&self, value: &T) -> Result<Option<sled::IVec>>
+    where
+        C: Codec<T>,
+        T: DbKey,
+    {
+        self.ensure_writable()?;
+        self.write(derive_key(value)?.into(), Some(C::encode(value)?.into()))
+    }
+    /// inserts value wrapped in an envelope recording its schema version
+    pub fn insert_versioned<T: Versioned + DbKey>(&self, value: &T) -> Result<Option<sled::IVec>> {
+        self.insert_with::<VersionedCodec, T>(value)
+    }
+    /// atomically swaps the value stored under key from old to new, serializing
+    /// both with borsh. `None` for old expects the key to be absent, while `None`
+    /// for new removes the key. when the stored value does not match old, the
+    /// inner `CompareAndSwapError` contains the current value so callers can retry
+    pub fn compare_and_swap<K, T>(
+        &self,
+        key: K,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) -> Result<std::result::Result<(), sled::CompareAndSwapError>>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize,
+    {
+        self.ensure_writable()?;
+        let old = old.map(serialize_value).transpose()?;
+        let new = new.map(serialize_value).transpose()?;
+        self.cas(key.as_ref(), old.as_deref(), new.map(IVec::from))
+    }
+    /// typed variant of `compare_and_swap`, whose inner `CasError` holds the
+    /// deserialized current value on a mismatch. a current value which fails
+    /// to be deserialized is returned as the outer `Deserialize` error
+    pub fn compare_and_swap_typed<K, T>(
+        &self,
+        key: K,
+        old: Option<&T>,
+        new: Option<&T>,
+    ) -> Result<std::result::Result<(), CasError<T>>>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize + BorshDeserialize,
+    {
+        match self.compare_and_swap(key, old, new)? {
+            Ok(()) => Ok(Ok(())),
+            Err(err) => Ok(Err(CasError {
+                current: err
+                    .current
+                    .map(|current| deserialize_value(&current))
+                    .transpose()?,
+            })),
         }
     }
-    pub fn insert<T>(&mut self, value: &T) -> Result<()>
+    /// atomically inserts value only if its key is absent or has expired,
+    /// returning true if this call inserted it and false if a value was already
+    /// stored. sled batches aren't conditional, so there is no `DbBatch` equivalent
+    pub fn insert_if_absent<T>(&self, value: &T) -> Result<bool>
     where
         T: BorshSerialize + DbKey,
     {
-        self.batch.insert(
-            value.key()?,
-            match borsh::to_vec(value) {
-                Ok(data) => data,
-                Err(err) => return Err(anyhow!("failed to insert entry into batch {:#?}", err)),
-            },
-        );
-        self.count += 1;
-        Ok(())
+        self.ensure_writable()?;
+        Ok(self
+            .cas(
+                &derive_key(value)?,
+                None,
+                Some(serialize_value(value)?.into()),
+            )?
+            .is_ok())
     }
-    /// returns the inner batch, and should only be used when the batch object
-    /// is finished with and the batch needs to be applied, as it replaces the inner
-    /// batch with its default version
-    pub fn take_inner(&mut self) -> sled::Batch {
-        std::mem::take(&mut self.batch)
+    /// returns the value stored under the key derived from value, atomically
+    /// inserting value first if the key is absent. concurrent callers racing
+    /// on the same key all observe the single value which was inserted
+    pub fn get_or_insert<T>(&self, value: &T) -> Result<T>
+    where
+        T: BorshSerialize + BorshDeserialize + DbKey,
+    {
+        self.ensure_writable()?;
+        let key = derive_key(value)?;
+        let data = IVec::from(serialize_value(value)?);
+        loop {
+            match self.cas(&key, None, Some(data.clone()))? {
+                Ok(()) => return deserialize_value(&data),
+                Err(sled::CompareAndSwapError {
+                    current: Some(current),
+                    ..
+                }) => return deserialize_value(&current),
+                // the existing value was removed before it could be read, so try again
+                Err(_) => continue,
+            }
+        }
     }
-    pub fn inner(&self) -> &sled::Batch {
-        &self.batch
+    /// atomically replaces the value stored under key with the result of f,
+    /// which receives the current value or None if the key is absent or has
+    /// expired. returning None from f removes the key. the write is a compare
+    /// and swap retried until no concurrent write intervened, so under
+    /// contention f may be invoked more than once and should be free of side
+    /// effects. returns the value stored, like sled's `update_and_fetch`
+    pub fn update<K, T, F>(&self, key: K, f: F) -> Result<Option<T>>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize + BorshDeserialize,
+        F: FnMut(Option<T>) -> Option<T>,
+    {
+        Ok(self.swap_with(key, f)?.1)
     }
-    pub fn count(&self) -> u64 {
-        self.count
+    /// like `update`, but returns the value stored before f was applied, like
+    /// sled's `fetch_and_update`
+    pub fn fetch_and_update<K, T, F>(&self, key: K, f: F) -> Result<Option<T>>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize + BorshDeserialize,
+        F: FnMut(Option<T>) -> Option<T>,
+    {
+        self.swap_with(key, f)?
+            .0
+            .map(|previous| deserialize_value(&previous))
+            .transpose()
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+    /// compare and swap loop shared by `update` and `fetch_and_update`, returning
+    /// the raw previous value and the value stored
+    fn swap_with<K, T, F>(&self, key: K, mut f: F) -> Result<(Option<IVec>, Option<T>)>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize + BorshDeserialize,
+        F: FnMut(Option<T>) -> Option<T>,
+    {
+        self.ensure_writable()?;
+        let key = key.as_ref();
+        loop {
+            let current = self.get(key)?;
+            let decoded = current
+                .as_ref()
+                .map(|value| deserialize_value(value))
+                .transpose()?;
+            let updated = f(decoded);
+            let data = updated.as_ref().map(serialize_value).transpose()?;
+            if self
+                .cas(key, current.as_deref(), data.map(IVec::from))?
+                .is_ok()
+            {
+                return Ok((current, updated));
+            }
+        }
+    }
+    /// inserts value, returning the previous value stored under its key. if the
+    /// previous value fails to be deserialized `Deserialize` is returned, which is
+    /// distinct from `Ok(None)` for a new key, although value is still inserted
+    pub fn replace<T>(&self, value: &T) -> Result<Option<T>>
+    where
+        T: BorshSerialize + BorshDeserialize + DbKey,
+    {
+        self.insert(value)?
+            .map(|previous| deserialize_value(&previous))
+            .transpose()
+    }
+    /// returns the value stored under key, atomically inserting the value
+    /// returned by f first if the key is absent. f is only invoked if the key is
+    /// absent when first read, and at most once, while concurrent callers racing
+    /// on the same key all observe the single value which was inserted
+    pub fn get_or_insert_with<K, T, F>(&self, key: K, f: F) -> Result<T>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize + BorshDeserialize,
+        F: FnOnce() -> T,
+    {
+        self.ensure_writable()?;
+        let key = key.as_ref();
+        if let Some(current) = self.get(key)? {
+            return deserialize_value(&current);
+        }
+        let data = IVec::from(serialize_value(&f())?);
+        loop {
+            match self.cas(key, None, Some(data.clone()))? {
+                Ok(()) => return deserialize_value(&data),
+                Err(sled::CompareAndSwapError {
+                    current: Some(current),
+                    ..
+                }) => return deserialize_value(&current),
+                // the existing value was removed before it could be read, so try again
+                Err(_) => continue,
+            }
+        }
+    }
+    /// removes the value stored under key, returning the previous value if any
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<sled::IVec>> {
+        self.ensure_writable()?;
+        self.write(key.as_ref().into(), None)
+    }
+    /// removes the entry whose key is derived from the given value
+    pub fn remove_value<T: DbKey>(&self, value: &T) -> Result<Option<sled::IVec>> {
+        self.ensure_writable()?;
+        self.write(derive_key(value)?.into(), None)
+    }
+    /// removes the value stored under key, returning it deserialized if any
+    pub fn remove_deserialize<K: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        key: K,
+    ) -> Result<Option<T>> {
+        self.remove(key)?
+            .map(|previous| deserialize_value(&previous))
+            .transpose()
+    }
+    /// subscribes to changes of keys starting with prefix, an empty prefix
+    /// subscribes to every key in the tree
+    pub fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> sled::Subscriber {
+        self.tree.watch_prefix(prefix)
+    }
+    /// subscribes to changes of keys starting with prefix, deserializing the
+    /// value of each insert
+    pub fn watch_prefix_deserialize<P: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        prefix: P,
+    ) -> TypedSubscriber<T> {
+        TypedSubscriber::new(self.tree.watch_prefix(prefix))
+    }
+    /// subscribes to changes of keys starting with prefix, yielding each as a
+    /// typed `watch::Event`. only the value type is generic, so it can be
+    /// called as `watch_prefix_typed::<T>(prefix)`
+    pub fn watch_prefix_typed<T: BorshDeserialize>(
+        &self,
+        prefix: impl AsRef<[u8]>,
+    ) -> EventSubscriber<T> {
+        EventSubscriber::new(self.watch_prefix_deserialize(prefix))
+    }
+    /// sets the merge operator used by `merge`, replacing any previously set
+    /// operator. it's also kept for the other handles of the tree to apply it
+    /// within a transaction, when merges are recorded in the cdc tree or may
+    /// clear deadlines
+    pub fn set_merge_operator(
+        &self,
+        merge_operator: impl sled::MergeOperator + Send + Sync + 'static,
+    ) {
+        let merge_operator: SharedMergeOperator = Arc::new(merge_operator);
+        self.merge_operators
+            .write()
+            .unwrap()
+            .insert(self.tree.name(), merge_operator.clone());
+        self.tree
+            .set_merge_operator(move |key: &[u8], old: Option<&[u8]>, delta: &[u8]| {
+                merge_operator(key, old, delta)
+            })
+    }
+    /// sets the tree's merge operator to f, deserializing the stored value and
+    /// the delta with borsh and serializing the result, with None removing the
+    /// key. sled's merge operators can't fail, so if either the stored value or
+    /// the delta fails to be deserialized the stored value is left unchanged.
+    /// deltas should be merged in with `merge_typed`
+    pub fn set_merge<T, F>(&self, f: F)
+    where
+        T: BorshSerialize + BorshDeserialize,
+        F: Fn(&[u8], Option<T>, T) -> Option<T> + Send + Sync + 'static,
+    {
+        self.set_merge_operator(move |key: &[u8], old: Option<&[u8]>, delta: &[u8]| {
+            let decoded = old.map(T::try_from_slice).transpose();
+            match (decoded, T::try_from_slice(delta)) {
+                (Ok(decoded), Ok(delta)) => f(key, decoded, delta).and_then(|merged| {
+                    // a result which can't be serialized is treated like a failed merge
+                    borsh::to_vec(&merged)
+                        .ok()
+                        .or_else(|| old.map(<[u8]>::to_vec))
+                }),
+                _ => old.map(<[u8]>::to_vec),
+            }
+        })
+    }
+    /// merges the borsh serialized delta into the value stored under key using
+    /// the merge function set with `set_merge`, returning the newly stored value
+    pub fn merge_typed<K, T>(&self, key: K, delta: &T) -> Result<Option<T>>
+    where
+        K: AsRef<[u8]>,
+        T: BorshSerialize + BorshDeserialize,
+    {
+        self.merge(key, serialize_value(delta)?)?
+            .map(|merged| deserialize_value(&merged))
+            .transpose()
+    }
+    /// merges value into the value stored under key using the tree's merge
+    /// operator, returning the newly stored value
+    pub fn merge<K, V>(&self, key: K, value: V) -> Result<Option<sled::IVec>>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.ensure_writable()?;
+        if !self.is_tracked() {
+            return Ok(self.tree.merge(key, value)?);
+        }
+        let merge_operator = self
+            .merge_operators
+            .read()
+            .unwrap()
+            .get(&self.tree.name())
+            .cloned()
+            .ok_or(SledUtilsError::InvalidOperation(
+                "merge operator wasn't set through set_merge_operator",
+            ))?;
+        let (key, value) = (key.as_ref(), value.as_ref());
+        transact(&[self], |tx_trees| {
+            let current = tx_trees[0].get(key)?;
+            let merged = merge_operator(key, current.as_deref(), value).map(IVec::from);
+            match &merged {
+                Some(merged) => tx_trees[0].insert(key, merged.clone())?,
+                None => tx_trees[0].remove(key)?,
+            };
+            Ok(merged)
+        })
+    }
+    /// atomically adds delta to the counter stored under key, returning the new
+    /// value. counters start at 0 and are stored as borsh encoded i64's, so they
+    /// can be read back with `deserialize`. the tree's merge operator must have
+    /// been set to `counter_merge` beforehand
+    pub fn increment<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        match self.merge(key, delta.to_le_bytes())? {
+            Some(value) => deserialize_value(&value),
+            None => Ok(0),
+        }
+    }
+    /// atomically removes every entry whose key starts with prefix in a single
+    /// batch, returning the number of entries removed. only keys present when
+    /// the prefix is scanned are removed, keys inserted concurrently after they
+    /// are scanned remain in the tree
+    pub fn remove_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<u64> {
+        self.ensure_writable()?;
+        let mut batch = DbBatch::new();
+        for key in self.tree.scan_prefix(prefix).keys() {
+            batch.remove(key?)?;
+        }
+        let count = batch.count();
+        self.write_batch(&mut batch)?;
+        Ok(count)
+    }
+    /// removes every entry from the tree, existing handles to the tree remain
+    /// usable. when writes are recorded in the cdc tree, or entries of the tree
+    /// have deadlines, every key is removed individually so each removal is
+    /// recorded
+    pub fn clear(&self) -> Result<()> {
+        self.ensure_writable()?;
+        if self.cdc.is_some() || self.deadlines.holds_any(&self.tree)? {
+            self.remove_prefix([])?;
+            return Ok(());
+        }
+        Ok(self.tree.clear()?)
+    }
+    /// returns the value stored under key. entries inserted with a ttl are
+    /// treated as absent once it elapses, though they are only removed by
+    /// `Database::purge_expired`, and are still visible to iterators until then
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> sled::Result<Option<sled::IVec>> {
+        match self.tree.get(&key)? {
+            Some(_) if self.deadlines.is_expired(&self.tree, key.as_ref())? => Ok(None),
+            value => Ok(value),
+        }
+    }
+    /// calls f with the bytes stored under key, borrowed from the value held by
+    /// sled without copying them, returning its result or None if the key is absent
+    pub fn get_raw<K, F, R>(&self, key: K, f: F) -> sled::Result<Option<R>>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(&[u8]) -> R,
+    {
+        Ok(self.get(key)?.map(|value| f(&value)))
+    }
+    /// deserializes the values stored under each of keys, in the same order,
+    /// with None for absent keys. sled has no multi key lookup, so this performs
+    /// one lookup per key, and fails on the first value which fails to be deserialized
+    pub fn get_many<K: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<Option<T>>> {
+        keys.iter().map(|key| self.get_deserialize(key)).collect()
+    }
+    /// deserializes the value stored under key, returning `Ok(None)` if the key is
+    /// absent and only erroring when the value fails to be deserialized
+    pub fn get_deserialize<K: AsRef<[u8]>, T>(&self, key: K) -> Result<Option<T>>
+    where
+        T: BorshDeserialize,
+    {
+        match self.get(key)? {
+            Some(value) => Ok(Some(deserialize_value(&value)?)),
+            None => Ok(None),
+        }
+    }
+    /// deserializes the value stored under key with the codec C rather than borsh,
+    /// returning `Ok(None)` if the key is absent
+    pub fn get_deserialize_with<C, K, T>(&self, key: K) -> Result<Option<T>>
+    where
+        C: Codec<T>,
+        K: AsRef<[u8]>,
+    {
+        match self.get(key)? {
+            Some(value) => Ok(Some(C::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+    /// deserializes the value stored under key, returning an error if the key is absent
+    pub fn deserialize<K: AsRef<[u8]>, T>(&self, key: K) -> Result<T>
+    where
+        T: BorshDeserialize,
+    {
+        let key = key.as_ref();
+        self.get_deserialize(key)?
+            .ok_or_else(|| SledUtilsError::KeyNotFound(key.into()))
+    }
+    /// deserializes the value stored under key with the codec C rather than borsh,
+    /// returning an error if the key is absent
+    pub fn deserialize_with<C, K, T>(&self, key: K) -> Result<T>
+    where
+        C: Codec<T>,
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        self.get_deserialize_with::<C, _, _>(key)?
+            .ok_or_else(|| SledUtilsError::KeyNotFound(key.into()))
+    }
+    /// deserializes the envelope stored under key, converting payloads written
+    /// with an earlier schema version through `Versioned::decode_version`.
+    /// returns an error if the key is absent
+    pub fn deserialize_versioned<K: AsRef<[u8]>, T: Versioned>(&self, key: K) -> Result<T> {
+        self.deserialize_with::<VersionedCodec, _, _>(key)
+    }
+    /// returns an iterator which lazily deserializes each value in the tree with
+    /// the codec C rather than borsh
+    pub fn iter_deserialize_with<C, T>(&self) -> impl Iterator<Item = Result<(IVec, T)>>
+    where
+        C: Codec<T>,
+    {
+        self.tree.iter().map(|entry| {
+            let (key, value) = entry?;
+            Ok((key, C::decode(&value)?))
+        })
+    }
+}
+
+/// merge operator used by `DbTree::increment`, adding the little-endian i64 delta
+/// to the stored counter. a stored value which isn't a valid counter is left unchanged
+pub fn counter_merge(_key: &[u8], old: Option<&[u8]>, delta: &[u8]) -> Option<Vec<u8>> {
+    let current = match old {
+        Some(old) => match <[u8; 8]>::try_from(old) {
+            Ok(old) => i64::from_le_bytes(old),
+            Err(_) => return Some(old.to_vec()),
+        },
+        None => 0,
+    };
+    let delta = match <[u8; 8]>::try_from(delta) {
+        Ok(delta) => i64::from_le_bytes(delta),
+        Err(_) => return old.map(|old| old.to_vec()),
+    };
+    Some(current.wrapping_add(delta).to_le_bytes().to_vec())
+}
+
+/// derives the key of a value being inserted into the db
+fn derive_key<T: DbKey>(value: &T) -> Result<Vec<u8>> {
+    value.key().map_err(SledUtilsError::Key)
+}
+
+/// serializes a value with borsh
+fn serialize_value<T: BorshSerialize>(value: &T) -> Result<Vec<u8>> {
+    borsh::to_vec(value).map_err(SledUtilsError::Serialize)
+}
+
+/// deserializes a value with borsh
+pub(crate) fn deserialize_value<T: BorshDeserialize>(value: &[u8]) -> Result<T> {
+    T::try_from_slice(value).map_err(SledUtilsError::Deserialize)
+}
+
+/// deserializes the value of an entry returned by a sled iterator
+fn deserialize_entry<T: BorshDeserialize>(entry: sled::Result<(IVec, IVec)>) -> Result<(IVec, T)> {
+    let (key, value) = entry?;
+    Ok((key, deserialize_value(&value)?))
+}
+
+/// decodes a key as utf8, mapping invalid keys to `DeserializeKey`
+fn decode_string_key(key: &[u8]) -> Result<String> {
+    String::from_utf8(key.to_vec()).map_err(|err| {
+        SledUtilsError::DeserializeKey(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    })
+}
+
+/// deserializes both the key and value of an entry returned by a sled iterator
+fn deserialize_typed_entry<K: BorshDeserialize, V: BorshDeserialize>(
+    entry: sled::Result<(IVec, IVec)>,
+) -> Result<(K, V)> {
+    let (key, value) = entry?;
+    let key = K::try_from_slice(&key).map_err(SledUtilsError::DeserializeKey)?;
+    Ok((key, deserialize_value(&value)?))
+}
+
+impl DbBatch {
+    pub fn new() -> DbBatch {
+        DbBatch {
+            batch: Default::default(),
+            writes: Default::default(),
+            insert_count: 0,
+            remove_count: 0,
+            auto_flush: None,
+        }
+    }
+    /// returns a batch which is automatically applied to tree, and reset, whenever
+    /// the number of queued operations reaches max_ops. this bounds the memory used
+    /// when bulk loading, at the cost of atomicity: each automatic flush is applied
+    /// separately, so a failure part way through leaves earlier flushes persisted.
+    /// `finish` must be called once done to apply any remaining operations
+    pub fn with_auto_flush(tree: Arc<DbTree>, max_ops: u64) -> DbBatch {
+        DbBatch {
+            batch: Default::default(),
+            writes: Default::default(),
+            insert_count: 0,
+            remove_count: 0,
+            auto_flush: Some((tree, max_ops.max(1))),
+        }
+    }
+    pub fn insert<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: BorshSerialize + DbKey,
+    {
+        let key = IVec::from(derive_key(value)?);
+        let value = IVec::from(serialize_value(value)?);
+        self.batch.insert(key.clone(), value.clone());
+        self.writes.insert(key, Some(value));
+        self.insert_count += 1;
+        self.maybe_auto_flush()
+    }
+    /// queues the insertion of a value serialized with the codec C rather than borsh
+    pub fn insert_with<C, T>(&mut self, value: &T) -> Result<()>
+    where
+        C: Codec<T>,
+        T: DbKey,
+    {
+        let key = IVec::from(derive_key(value)?);
+        let value = IVec::from(C::encode(value)?);
+        self.batch.insert(key.clone(), value.clone());
+        self.writes.insert(key, Some(value));
+        self.insert_count += 1;
+        self.maybe_auto_flush()
+    }
+    /// queues every value which can be inserted, returning the number queued and
+    /// the index of each value which failed along with its error. a failing value
+    /// doesn't abort the remaining values, and queued operations stay in the batch
+    pub fn try_insert_all<T, I>(&mut self, values: I) -> (u64, Vec<(usize, SledUtilsError)>)
+    where
+        T: BorshSerialize + DbKey,
+        I: IntoIterator<Item = T>,
+    {
+        let mut queued = 0;
+        let mut errors = Vec::new();
+        for (idx, value) in values.into_iter().enumerate() {
+            match self.insert(&value) {
+                Ok(()) => queued += 1,
+                Err(err) => errors.push((idx, err)),
+            }
+        }
+        (queued, errors)
+    }
+    /// queues the removal of key, counting towards the total operations in the batch
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Result<()> {
+        let key = IVec::from(key.as_ref());
+        self.batch.remove(key.clone());
+        self.writes.insert(key, None);
+        self.remove_count += 1;
+        self.maybe_auto_flush()
+    }
+    /// queues the removal of the key derived from value
+    pub fn remove_value<T: DbKey>(&mut self, value: &T) -> Result<()> {
+        self.remove(derive_key(value)?)
+    }
+    /// applies any operations remaining in an auto flushing batch to its tree
+    pub fn finish(&mut self) -> Result<()> {
+        match self.auto_flush.clone() {
+            Some((tree, _)) => self.flush_to(&tree),
+            None => Err(SledUtilsError::InvalidOperation(
+                "batch was not created with auto flush",
+            )),
+        }
+    }
+    fn maybe_auto_flush(&mut self) -> Result<()> {
+        match self.auto_flush.clone() {
+            Some((tree, max_ops)) if self.count() >= max_ops => self.flush_to(&tree),
+            _ => Ok(()),
+        }
+    }
+    fn flush_to(&mut self, tree: &DbTree) -> Result<()> {
+        tree.apply_batch(self)?;
+        self.insert_count = 0;
+        self.remove_count = 0;
+        Ok(())
+    }
+    /// returns the inner batch, and should only be used when the batch object
+    /// is finished with and the batch needs to be applied, as it replaces the inner
+    /// batch with its default version
+    pub fn take_inner(&mut self) -> sled::Batch {
+        self.writes.clear();
+        std::mem::take(&mut self.batch)
+    }
+    pub fn inner(&self) -> &sled::Batch {
+        &self.batch
+    }
+    /// returns the total number of operations (inserts and removes) queued in the batch
+    pub fn count(&self) -> u64 {
+        self.insert_count + self.remove_count
+    }
+    /// returns the number of inserts queued in the batch
+    pub fn insert_count(&self) -> u64 {
+        self.insert_count
+    }
+    /// returns the number of removes queued in the batch
+    pub fn remove_count(&self) -> u64 {
+        self.remove_count
+    }
+    /// returns true if no operations are queued, in which case applying the
+    /// batch can be skipped
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+}
+
+impl TreeBatch {
+    pub fn insert<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: BorshSerialize + DbKey,
+    {
+        self.batch.insert(value)
+    }
+    /// queues the removal of key
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> Result<()> {
+        self.batch.remove(key)
+    }
+    /// queues the removal of the key derived from value
+    pub fn remove_value<T: DbKey>(&mut self, value: &T) -> Result<()> {
+        self.batch.remove_value(value)
+    }
+    /// returns the total number of operations queued in the batch
+    pub fn count(&self) -> u64 {
+        self.batch.count()
+    }
+    /// returns true if no operations are queued in the batch
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+    /// returns the tree the batch is bound to
+    pub fn tree(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+    /// atomically applies the batch to the tree it was created from
+    pub fn commit(mut self) -> Result<()> {
+        self.tree.apply_batch(&mut self.batch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+    use codec::BorshCodec;
     use config::DbOpts;
+    use sled::transaction::ConflictableTransactionError;
     use std::fs::remove_dir_all;
+    use std::iter::Rev;
+    use types::CompositeKey;
 
     #[derive(BorshSerialize, BorshDeserialize, BorshSchema)]
     pub struct TestData {
@@ -217,12 +1879,44 @@ mod test {
         }
     }
 
+    impl UniqueKey for TestData {
+        fn unique_key(&self) -> anyhow::Result<Vec<u8>> {
+            Ok(self.foo.as_bytes().to_vec())
+        }
+    }
+
+    /// returns a path under the temp dir unique to the named test and this process
+    fn temp_db_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("sled-utils-test-{}-{}", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// opens the database at the path of db_opts, retrying while sled's background
+    /// threads release the file lock held by a previously dropped handle
+    fn reopen(db_opts: &DbOpts) -> Arc<Database> {
+        retry_locked(|| Database::new(db_opts)).unwrap()
+    }
+
+    /// calls open until it stops failing to lock the database, which sled's io
+    /// threads hold for a short while after the previous handle is dropped
+    fn retry_locked<T>(open: impl Fn() -> Result<T>) -> Result<T> {
+        for _ in 0..100 {
+            match open() {
+                Err(SledUtilsError::Sled(sled::Error::Io(_))) => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                result => return result,
+            }
+        }
+        open()
+    }
+
     // performs very basic database testing
     #[test]
     fn test_db_basic() {
-        let db_opts = DbOpts::default();
-
-        let db = Database::new(&db_opts).unwrap();
+        let db = Database::new_temporary().unwrap();
         let insert = || {
             let mut db_batch = DbBatch::new();
             db_batch
@@ -302,6 +1996,3418 @@ mod test {
         insert();
         query();
         db.destroy();
-        remove_dir_all("test_infos.db").unwrap();
+    }
+
+    #[test]
+    fn test_db_remove() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("remove")).unwrap();
+        let data = TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        };
+        tree.insert(&data).unwrap();
+        assert_eq!(tree.len(), 1);
+
+        // removing a present key returns the previous value
+        let previous = tree.remove("key1").unwrap().unwrap();
+        let previous: TestData = BorshDeserialize::try_from_slice(&previous).unwrap();
+        assert_eq!(previous.foo, "foo1".to_string());
+        assert!(tree.is_empty());
+
+        // removing an absent key is a no-op
+        assert!(tree.remove("key1").unwrap().is_none());
+        tree.insert(&data).unwrap();
+        assert!(tree.remove_value(&data).unwrap().is_some());
+        assert!(tree.remove_value(&data).unwrap().is_none());
+
+        // removals queued in a batch count towards total operations
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data).unwrap();
+        db_batch
+            .insert(&TestData {
+                key: "key2".to_string(),
+                foo: "foo2".to_string(),
+            })
+            .unwrap();
+        tree.apply_batch(&mut db_batch).unwrap();
+        assert_eq!(tree.len(), 2);
+        db_batch.remove("key1").unwrap();
+        assert_eq!(db_batch.count(), 3);
+        tree.apply_batch(&mut db_batch).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert!(!tree.contains_key("key1").unwrap());
+        assert!(tree.contains_key("key2").unwrap());
+    }
+
+    #[test]
+    fn test_db_iter_deserialize() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("iter")).unwrap();
+        for i in 0..3 {
+            tree.insert(&TestData {
+                key: format!("key{}", i),
+                foo: format!("foo{}", i),
+            })
+            .unwrap();
+        }
+        let values = tree
+            .iter_deserialize::<TestData>()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(values.len(), 3);
+        for (i, (key, value)) in values.iter().enumerate() {
+            assert_eq!(key.as_ref(), format!("key{}", i).as_bytes());
+            assert_eq!(value.foo, format!("foo{}", i));
+        }
+        let values = db
+            .list_deserialized::<TestData>(DbTrees::Custom("iter"))
+            .unwrap();
+        assert_eq!(values.len(), 3);
+
+        // malformed rows surface as errors instead of being dropped
+        tree.tree.insert("key3", vec![255_u8; 3]).unwrap();
+        let results = tree.iter_deserialize::<TestData>().collect::<Vec<_>>();
+        assert_eq!(results.len(), 4);
+        assert!(results[3].is_err());
+        assert!(db
+            .list_deserialized::<TestData>(DbTrees::Custom("iter"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_db_scan_prefix() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("scan_prefix")).unwrap();
+        for key in [
+            "user:2:session:1",
+            "user:1:session:2",
+            "user:2:session:0",
+            "user:1:session:1",
+        ] {
+            tree.insert(&TestData {
+                key: key.to_string(),
+                foo: key.to_string(),
+            })
+            .unwrap();
+        }
+        let keys = tree
+            .scan_prefix("user:1:")
+            .map(|entry| entry.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                IVec::from("user:1:session:1"),
+                IVec::from("user:1:session:2")
+            ]
+        );
+        let values = tree
+            .scan_prefix_deserialize::<_, TestData>("user:2:")
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].1.foo, "user:2:session:0".to_string());
+        assert_eq!(values[1].1.foo, "user:2:session:1".to_string());
+        assert_eq!(tree.scan_prefix("user:3:").count(), 0);
+    }
+
+    #[test]
+    fn test_db_range() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("range")).unwrap();
+        for timestamp in [10_u64, 20, 30, 40] {
+            tree.tree
+                .insert(timestamp.to_be_bytes(), borsh::to_vec(&timestamp).unwrap())
+                .unwrap();
+        }
+        let keys_of = |iter: sled::Iter| {
+            iter.map(|entry| {
+                let (key, _) = entry.unwrap();
+                u64::from_be_bytes(key.as_ref().try_into().unwrap())
+            })
+            .collect::<Vec<_>>()
+        };
+        let start = 20_u64.to_be_bytes();
+        let end = 40_u64.to_be_bytes();
+        assert_eq!(keys_of(tree.range(start..end)), vec![20, 30]);
+        assert_eq!(keys_of(tree.range(start..=end)), vec![20, 30, 40]);
+        assert_eq!(keys_of(tree.range(start..)), vec![20, 30, 40]);
+        assert_eq!(keys_of(tree.range(..end)), vec![10, 20, 30]);
+        let values = tree
+            .range_deserialize::<_, _, u64>(start..end)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            values
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>(),
+            vec![20, 30]
+        );
+    }
+
+    #[test]
+    fn test_db_compare_and_swap() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("cas")).unwrap();
+        let first = TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        };
+        let second = TestData {
+            key: "key1".to_string(),
+            foo: "foo2".to_string(),
+        };
+        tree.compare_and_swap("key1", None, Some(&first))
+            .unwrap()
+            .unwrap();
+        tree.compare_and_swap("key1", Some(&first), Some(&second))
+            .unwrap()
+            .unwrap();
+        // the expected old value is now stale, so the swap fails with the current value
+        let err = tree
+            .compare_and_swap("key1", Some(&first), Some(&second))
+            .unwrap()
+            .unwrap_err();
+        let current: TestData = BorshDeserialize::try_from_slice(&err.current.unwrap()).unwrap();
+        assert_eq!(current.foo, "foo2".to_string());
+        assert_eq!(err.proposed.unwrap(), borsh::to_vec(&second).unwrap());
+
+        // a new value of None removes the key
+        tree.compare_and_swap::<_, TestData>("key1", Some(&second), None)
+            .unwrap()
+            .unwrap();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_db_transaction() {
+        let db = Database::new_temporary().unwrap();
+        let trees = [DbTrees::Custom("index"), DbTrees::Custom("data")];
+        db.transaction(&trees, |tx_trees| {
+            tx_trees[0].insert("foo1", "key1")?;
+            tx_trees[1].insert("key1", "foo1")?;
+            Ok::<_, ConflictableTransactionError<()>>(())
+        })
+        .unwrap();
+        let index = db.open_tree(trees[0]).unwrap();
+        let data = db.open_tree(trees[1]).unwrap();
+        assert_eq!(index.get("foo1").unwrap().unwrap(), IVec::from("key1"));
+        assert_eq!(data.get("key1").unwrap().unwrap(), IVec::from("foo1"));
+
+        // aborting discards the writes made to both trees
+        let result = db.transaction(&trees, |tx_trees| {
+            tx_trees[0].insert("foo2", "key2")?;
+            tx_trees[1].insert("key2", "foo2")?;
+            tx_trees[1].remove("key1")?;
+            sled::transaction::abort::<(), _>("aborted")
+        });
+        assert_eq!(result, Err(TransactionError::Abort("aborted")));
+        assert_eq!(index.len(), 1);
+        assert_eq!(data.len(), 1);
+        assert!(data.contains_key("key1").unwrap());
+        assert!(!data.contains_key("key2").unwrap());
+    }
+
+    #[test]
+    fn test_db_batch_auto_flush() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("auto_flush")).unwrap();
+        let max_ops = 10;
+        let mut db_batch = DbBatch::with_auto_flush(tree.clone(), max_ops);
+        for i in 0..max_ops * 2 + 1 {
+            db_batch
+                .insert(&TestData {
+                    key: format!("key{}", i),
+                    foo: format!("foo{}", i),
+                })
+                .unwrap();
+            // every max_ops inserts the batch is applied and reset
+            assert_eq!(tree.len() as u64, (i + 1) / max_ops * max_ops);
+        }
+        assert_eq!(db_batch.count(), 1);
+        db_batch.finish().unwrap();
+        assert_eq!(db_batch.count(), 0);
+        assert_eq!(tree.len() as u64, max_ops * 2 + 1);
+        assert!(DbBatch::new().finish().is_err());
+    }
+
+    #[test]
+    fn test_db_get_deserialize() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("get_deserialize")).unwrap();
+        tree.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        let value: TestData = tree.get_deserialize("key1").unwrap().unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+        assert!(tree
+            .get_deserialize::<_, TestData>("key2")
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            tree.deserialize::<_, TestData>("key2"),
+            Err(SledUtilsError::KeyNotFound(key)) if key == "key2"
+        ));
+
+        // corrupt data is an error rather than a missing value
+        tree.tree.insert("key3", vec![255_u8; 3]).unwrap();
+        assert!(matches!(
+            tree.get_deserialize::<_, TestData>("key3"),
+            Err(SledUtilsError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_db_clear() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("clear")).unwrap();
+        let other = db.open_tree(DbTrees::Custom("other")).unwrap();
+        let insert = |tree: &DbTree, count: usize| {
+            for i in 0..count {
+                tree.insert(&TestData {
+                    key: format!("key{}", i),
+                    foo: format!("foo{}", i),
+                })
+                .unwrap();
+            }
+        };
+        insert(&tree, 3);
+        insert(&other, 1);
+        tree.clear().unwrap();
+        assert_eq!(tree.len(), 0);
+        assert!(tree.is_empty());
+        assert_eq!(other.len(), 1);
+
+        // the handle is still usable after clearing
+        insert(&tree, 2);
+        assert_eq!(tree.len(), 2);
+        db.clear_tree(DbTrees::Custom("clear")).unwrap();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_db_composite_key() {
+        #[derive(BorshSerialize, BorshDeserialize)]
+        struct Session {
+            user_id: u64,
+            session_id: u32,
+        }
+
+        impl DbKey for Session {
+            fn key(&self) -> anyhow::Result<Vec<u8>> {
+                Ok(CompositeKey::new()
+                    .bytes("session")
+                    .separator()
+                    .u64(self.user_id)
+                    .u32(self.session_id)
+                    .into_bytes())
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("composite")).unwrap();
+        // with decimal strings user 10 would sort before user 9
+        for (user_id, session_id) in [(10, 1), (9, 300), (9, 2)] {
+            tree.insert(&Session {
+                user_id,
+                session_id,
+            })
+            .unwrap();
+        }
+        let sessions = tree
+            .iter_deserialize::<Session>()
+            .map(|entry| {
+                let (_, session) = entry.unwrap();
+                (session.user_id, session.session_id)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sessions, vec![(9, 2), (9, 300), (10, 1)]);
+        assert!(CompositeKey::new().u64(9).u32(300) < CompositeKey::new().u64(10).u32(1));
+    }
+
+    #[test]
+    fn test_db_codec() {
+        // stores TestData as `key\0foo` to exercise a non borsh codec
+        struct NulSeparatedCodec;
+
+        impl Codec<TestData> for NulSeparatedCodec {
+            fn encode(value: &TestData) -> Result<Vec<u8>> {
+                Ok(format!("{}\0{}", value.key, value.foo).into_bytes())
+            }
+            fn decode(bytes: &[u8]) -> Result<TestData> {
+                let invalid = || {
+                    SledUtilsError::Deserialize(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid test data",
+                    ))
+                };
+                let value = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+                let (key, foo) = value.split_once('\0').ok_or_else(invalid)?;
+                Ok(TestData {
+                    key: key.to_string(),
+                    foo: foo.to_string(),
+                })
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("codec")).unwrap();
+        let borsh_data = TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        };
+        let nul_data = TestData {
+            key: "key2".to_string(),
+            foo: "foo2".to_string(),
+        };
+        tree.insert_with::<BorshCodec, _>(&borsh_data).unwrap();
+        tree.insert_with::<NulSeparatedCodec, _>(&nul_data).unwrap();
+        assert_eq!(tree.get("key2").unwrap().unwrap(), IVec::from("key2\0foo2"));
+
+        let value: TestData = tree
+            .get_deserialize_with::<BorshCodec, _, _>("key1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+        // the borsh codec matches the default serialization
+        let value: TestData = tree.deserialize("key1").unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+        let value: TestData = tree
+            .get_deserialize_with::<NulSeparatedCodec, _, _>("key2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(value.key, "key2".to_string());
+        assert_eq!(value.foo, "foo2".to_string());
+        assert!(tree
+            .get_deserialize_with::<NulSeparatedCodec, _, TestData>("key3")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_db_increment() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("counters")).unwrap();
+        tree.set_merge_operator(counter_merge);
+        assert_eq!(tree.increment("counter", 5).unwrap(), 5);
+        assert_eq!(tree.increment("counter", -7).unwrap(), -2);
+        assert_eq!(tree.deserialize::<_, i64>("counter").unwrap(), -2);
+
+        let threads = (0..8)
+            .map(|_| {
+                let tree = tree.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        tree.increment("concurrent", 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(tree.deserialize::<_, i64>("concurrent").unwrap(), 800);
+    }
+
+    #[test]
+    fn test_db_watch_prefix() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("watch")).unwrap();
+        let timeout = std::time::Duration::from_secs(5);
+        let mut subscriber = tree.watch_prefix_deserialize::<_, TestData>("key");
+        let mut raw_subscriber = tree.watch_prefix("");
+        let mut default_subscriber = db.watch_prefix_deserialize::<_, TestData>("");
+        let writer = {
+            let tree = tree.clone();
+            std::thread::spawn(move || {
+                tree.insert(&TestData {
+                    key: "other".to_string(),
+                    foo: "foo0".to_string(),
+                })
+                .unwrap();
+                tree.insert(&TestData {
+                    key: "key1".to_string(),
+                    foo: "foo1".to_string(),
+                })
+                .unwrap();
+                tree.remove("key1").unwrap();
+            })
+        };
+        // the first matching event is the insert of key1, as other lacks the prefix
+        let (key, value) = subscriber.next_timeout(timeout).unwrap().unwrap();
+        assert_eq!(key, IVec::from("key1"));
+        assert_eq!(value.unwrap().foo, "foo1".to_string());
+        let (key, value) = subscriber.next_timeout(timeout).unwrap().unwrap();
+        assert_eq!(key, IVec::from("key1"));
+        assert!(value.is_none());
+        match raw_subscriber.next_timeout(timeout).unwrap() {
+            sled::Event::Insert { key, .. } => assert_eq!(key, IVec::from("other")),
+            sled::Event::Remove { .. } => panic!("expected insert"),
+        }
+        writer.join().unwrap();
+
+        // the default tree is watchable as well
+        db.inner()
+            .insert(
+                "key2",
+                borsh::to_vec(&TestData {
+                    key: "key2".to_string(),
+                    foo: "foo2".to_string(),
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        let (key, value) = default_subscriber.next_timeout(timeout).unwrap().unwrap();
+        assert_eq!(key, IVec::from("key2"));
+        assert_eq!(value.unwrap().foo, "foo2".to_string());
+    }
+
+    #[test]
+    fn test_db_watch_prefix_typed() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("watch")).unwrap();
+        let timeout = std::time::Duration::from_secs(5);
+        let mut subscriber = tree.watch_prefix_typed::<TestData>("key");
+        tree.insert(&TestData {
+            key: "other".to_string(),
+            foo: "foo0".to_string(),
+        })
+        .unwrap();
+        tree.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        tree.remove("key1").unwrap();
+        match subscriber.next_timeout(timeout).unwrap().unwrap() {
+            watch::Event::Insert(value) => assert_eq!(value.foo, "foo1"),
+            watch::Event::Remove(_) => panic!("expected insert"),
+        }
+        match subscriber.next().unwrap().unwrap() {
+            watch::Event::Remove(key) => assert_eq!(key, IVec::from("key1")),
+            watch::Event::Insert(_) => panic!("expected remove"),
+        }
+        // a value which can't be deserialized is reported as an error
+        tree.tree.insert("key2", vec![1]).unwrap();
+        assert!(subscriber.next_timeout(timeout).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_db_read_only() {
+        let mut db_opts = DbOpts {
+            path: temp_db_path("read_only"),
+            ..Default::default()
+        };
+        let data = TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        };
+        {
+            let db = Database::new(&db_opts).unwrap();
+            let tree = db.open_tree(DbTrees::Custom("read_only")).unwrap();
+            tree.insert(&data).unwrap();
+            db.flush().unwrap();
+        }
+
+        db_opts.read_only = true;
+        let db = reopen(&db_opts);
+        assert!(db.is_read_only());
+        let tree = db.open_tree(DbTrees::Custom("read_only")).unwrap();
+        assert!(tree.is_read_only());
+        // reads still succeed
+        let value: TestData = tree.deserialize("key1").unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+        // while writes fail without touching the tree
+        assert!(matches!(tree.insert(&data), Err(SledUtilsError::ReadOnly)));
+        assert!(matches!(tree.remove("key1"), Err(SledUtilsError::ReadOnly)));
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data).unwrap();
+        assert!(matches!(
+            tree.apply_batch(&mut db_batch),
+            Err(SledUtilsError::ReadOnly)
+        ));
+        assert!(matches!(tree.clear(), Err(SledUtilsError::ReadOnly)));
+        assert_eq!(tree.len(), 1);
+        // missing trees are not created
+        assert!(matches!(
+            db.open_tree(DbTrees::Custom("missing")),
+            Err(SledUtilsError::Sled(sled::Error::CollectionNotFound(_)))
+        ));
+        drop(tree);
+        drop(db);
+
+        db_opts.read_only = false;
+        let db = reopen(&db_opts);
+        assert!(!db
+            .inner()
+            .tree_names()
+            .iter()
+            .any(|name| *name == "missing"));
+        db.destroy();
+        drop(db);
+        remove_dir_all(&db_opts.path).unwrap();
+    }
+
+    #[test]
+    fn test_db_export_import() {
+        let source = Database::new_temporary().unwrap();
+        let tree = source.open_tree(DbTrees::Custom("export")).unwrap();
+        tree.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        // binary keys and empty values must round trip exactly
+        tree.tree.insert(vec![0_u8, 255, 0], vec![]).unwrap();
+        source
+            .open_tree(DbTrees::Custom("empty"))
+            .unwrap()
+            .tree
+            .insert("key", vec![])
+            .unwrap();
+        source.inner().insert("default", "value").unwrap();
+
+        let mut export = Vec::new();
+        source.export(&mut export).unwrap();
+        let target = Database::new_temporary().unwrap();
+        target.import(export.as_slice()).unwrap();
+
+        let mut source_names = source.inner().tree_names();
+        let mut target_names = target.inner().tree_names();
+        source_names.sort();
+        target_names.sort();
+        assert_eq!(source_names, target_names);
+        for name in source_names {
+            let source_entries = source
+                .inner()
+                .open_tree(&name)
+                .unwrap()
+                .iter()
+                .collect::<sled::Result<Vec<_>>>()
+                .unwrap();
+            let target_entries = target
+                .inner()
+                .open_tree(&name)
+                .unwrap()
+                .iter()
+                .collect::<sled::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(source_entries, target_entries);
+        }
+        assert!(target.import(&b"garbage"[..]).is_err());
+    }
+
+    #[test]
+    fn test_db_first_last() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("first_last")).unwrap();
+        assert!(tree.first().unwrap().is_none());
+        assert!(tree.last().unwrap().is_none());
+        assert!(tree.first_deserialize::<TestData>().unwrap().is_none());
+        assert!(tree.last_deserialize::<TestData>().unwrap().is_none());
+
+        // lexicographic byte order places "key10" between "key1" and "key2"
+        for key in ["key2", "key10", "key1"] {
+            tree.insert(&TestData {
+                key: key.to_string(),
+                foo: format!("foo_{}", key),
+            })
+            .unwrap();
+        }
+        assert_eq!(tree.first().unwrap().unwrap().0, IVec::from("key1"));
+        assert_eq!(tree.last().unwrap().unwrap().0, IVec::from("key2"));
+        let (key, value) = tree.first_deserialize::<TestData>().unwrap().unwrap();
+        assert_eq!(key, IVec::from("key1"));
+        assert_eq!(value.foo, "foo_key1".to_string());
+        let (key, value) = tree.last_deserialize::<TestData>().unwrap().unwrap();
+        assert_eq!(key, IVec::from("key2"));
+        assert_eq!(value.foo, "foo_key2".to_string());
+    }
+
+    #[test]
+    fn test_db_iter_rev() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("iter_rev")).unwrap();
+        assert_eq!(tree.iter_rev().count(), 0);
+        assert_eq!(tree.range_rev::<&[u8], _>(..).count(), 0);
+
+        for sequence in 0_u64..5 {
+            tree.tree
+                .insert(sequence.to_be_bytes(), borsh::to_vec(&sequence).unwrap())
+                .unwrap();
+        }
+        let sequences_of = |iter: Rev<sled::Iter>| {
+            iter.map(|entry| {
+                let (key, _) = entry.unwrap();
+                u64::from_be_bytes(key.as_ref().try_into().unwrap())
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(sequences_of(tree.iter_rev()), vec![4, 3, 2, 1, 0]);
+        assert_eq!(
+            sequences_of(tree.range_rev(1_u64.to_be_bytes()..4_u64.to_be_bytes())),
+            vec![3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_db_get_or_insert() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("get_or_insert")).unwrap();
+        let value = tree
+            .get_or_insert(&TestData {
+                key: "config".to_string(),
+                foo: "default".to_string(),
+            })
+            .unwrap();
+        assert_eq!(value.foo, "default".to_string());
+        let value = tree
+            .get_or_insert(&TestData {
+                key: "config".to_string(),
+                foo: "other".to_string(),
+            })
+            .unwrap();
+        assert_eq!(value.foo, "default".to_string());
+
+        // two threads racing on the same key both get the winning value back
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let threads = (0..2)
+            .map(|i| {
+                let tree = tree.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    tree.get_or_insert(&TestData {
+                        key: "race".to_string(),
+                        foo: format!("thread{}", i),
+                    })
+                    .unwrap()
+                    .foo
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results[0], results[1]);
+        let stored: TestData = tree.deserialize("race").unwrap();
+        assert_eq!(stored.foo, results[0]);
+    }
+
+    #[test]
+    fn test_db_size_on_disk() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("size")).unwrap();
+        let insert = |range: std::ops::Range<usize>| {
+            for i in range {
+                tree.insert(&TestData {
+                    key: format!("key{}", i),
+                    foo: "foo".repeat(100),
+                })
+                .unwrap();
+            }
+            db.flush().unwrap();
+        };
+        insert(0..100);
+        let size = db.size_on_disk().unwrap();
+        assert!(size > 0);
+        insert(100..1000);
+        assert!(db.size_on_disk().unwrap() > size);
+    }
+
+    #[test]
+    fn test_db_keys() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("keys")).unwrap();
+        for i in 0..5 {
+            tree.insert(&TestData {
+                key: format!("key{}", i),
+                foo: format!("foo{}", i),
+            })
+            .unwrap();
+        }
+        let keys = tree.keys().collect::<sled::Result<Vec<_>>>().unwrap();
+        let expected = db
+            .list_values(DbTrees::Custom("keys"))
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(keys, expected);
+        assert_eq!(db.list_keys(DbTrees::Custom("keys")).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_db_namespaced_trees() {
+        let db = Database::new_temporary().unwrap();
+        let users_a = db
+            .open_tree(DbTrees::Namespaced {
+                ns: "a",
+                name: "users",
+            })
+            .unwrap();
+        let users_b = db
+            .open_tree(DbTrees::Namespaced {
+                ns: "b",
+                name: "users",
+            })
+            .unwrap();
+        users_a
+            .insert(&TestData {
+                key: "key1".to_string(),
+                foo: "foo1".to_string(),
+            })
+            .unwrap();
+        assert_eq!(users_a.len(), 1);
+        assert!(users_b.is_empty());
+        assert_eq!(
+            users_a.tree.name(),
+            IVec::from("__sled_utils_tree:1:a:users")
+        );
+        assert_eq!(
+            users_b.tree.name(),
+            IVec::from("__sled_utils_tree:1:b:users")
+        );
+        assert_eq!(
+            DbTrees::Namespaced {
+                ns: "a",
+                name: "users"
+            }
+            .to_string(),
+            "__sled_utils_tree:1:a:users".to_string()
+        );
+        // splits sharing a concatenation map to distinct tree names
+        let splits = [("a", "b:c"), ("a:b", "c"), ("a:", "b:c"), ("", "a:b:c")];
+        let names = splits
+            .iter()
+            .map(|(ns, name)| DbTrees::Namespaced { ns, name }.to_string())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(names.len(), splits.len());
+        for (ns, name) in splits {
+            let tree_name = DbTrees::Namespaced { ns, name }.to_string();
+            assert_eq!(DbTrees::parse(&tree_name), Some((ns, name)));
+        }
+        assert_eq!(DbTrees::parse("users"), None);
+        assert_eq!(DbTrees::parse("a:users"), None);
+        assert_eq!(DbTrees::parse("1:a:users"), None);
+        assert_eq!(DbTrees::parse("__sled_utils_tree:9:a:users"), None);
+        for (tree, name) in [
+            (DbTrees::Metadata, "metadata"),
+            (DbTrees::Index, "index"),
+            (DbTrees::Data, "data"),
+        ] {
+            assert_eq!(
+                db.open_tree(tree).unwrap().tree.name(),
+                IVec::from(format!("{}{}", types::RESERVED_PREFIX, name).as_str())
+            );
+        }
+
+        // custom names can't alias the built-in or namespaced trees
+        let data = db.open_tree(DbTrees::Data).unwrap();
+        data.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        let aliases = [
+            "data".to_string(),
+            DbTrees::Data.to_string(),
+            DbTrees::Namespaced {
+                ns: "a",
+                name: "users",
+            }
+            .to_string(),
+        ];
+        for alias in &aliases {
+            let tree = db.open_tree(DbTrees::Custom(alias)).unwrap();
+            assert!(tree.is_empty());
+            assert_ne!(tree.tree.name(), data.tree.name());
+            assert_ne!(tree.tree.name(), users_a.tree.name());
+        }
+        // escaped names stay distinct from each other
+        let escaped = aliases
+            .iter()
+            .map(|alias| DbTrees::Custom(alias).to_string())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(escaped.len(), aliases.len());
+
+        // trees reopened internally by name keep their reserved names
+        users_b
+            .insert_with_ttl(
+                &TestData {
+                    key: "key1".to_string(),
+                    foo: "foo1".to_string(),
+                },
+                Duration::ZERO,
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(db.purge_expired().unwrap(), 1);
+        assert!(users_b.tree.is_empty());
+        let migrator = Migrator::new().register(DbTrees::Data, |bytes: &[u8]| {
+            let mut value: TestData = deserialize_value(bytes)?;
+            value.foo = "migrated".to_string();
+            serialize_value(&value)
+        });
+        assert_eq!(migrator.run(&db).unwrap(), 1);
+        assert_eq!(
+            data.deserialize::<_, TestData>("key1").unwrap().foo,
+            "migrated"
+        );
+    }
+
+    #[test]
+    fn test_db_remove_prefix() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("remove_prefix")).unwrap();
+        for key in [
+            "expired:1",
+            "expired:2",
+            "expired:3",
+            "active:1",
+            "active:2",
+        ] {
+            tree.insert(&TestData {
+                key: key.to_string(),
+                foo: key.to_string(),
+            })
+            .unwrap();
+        }
+        assert_eq!(tree.remove_prefix("expired:").unwrap(), 3);
+        assert_eq!(
+            tree.keys().collect::<sled::Result<Vec<_>>>().unwrap(),
+            vec![IVec::from("active:1"), IVec::from("active:2")]
+        );
+        assert_eq!(tree.remove_prefix("expired:").unwrap(), 0);
+    }
+
+    /// drives future to completion on the current thread
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        struct ThreadWaker(std::thread::Thread);
+
+        impl std::task::Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = std::task::Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = std::task::Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                std::task::Poll::Ready(output) => return output,
+                std::task::Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_db_flush_async() {
+        let db_opts = DbOpts {
+            path: temp_db_path("flush_async"),
+            // keep the background flusher from flushing the writes first
+            flush_every_ms: Some(0),
+            temporary: true,
+            ..Default::default()
+        };
+        let db = Database::new(&db_opts).unwrap();
+        let tree = db.open_tree(DbTrees::Custom("flush_async")).unwrap();
+        tree.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        assert!(block_on(tree.flush_async()).unwrap() > 0);
+        tree.insert(&TestData {
+            key: "key2".to_string(),
+            foo: "foo2".to_string(),
+        })
+        .unwrap();
+        assert!(block_on(db.flush_async()).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_db_opts_into_config() {
+        let db_opts = DbOpts {
+            path: "test_opts_into_config.db".to_string(),
+            flush_every_ms: Some(1000),
+            snapshot_after_ops: Some(100),
+            temporary: true,
+            ..Default::default()
+        };
+        let sled_config: sled::Config = (&db_opts).into();
+        assert_eq!(sled_config.flush_every_ms, Some(1000));
+        assert!(sled_config.temporary);
+        drop(sled_config);
+
+        let db_opts = DbOpts {
+            flush_every_ms: Some(0),
+            ..db_opts
+        };
+        let sled_config: sled::Config = (&db_opts).into();
+        assert_eq!(sled_config.flush_every_ms, None);
+        drop(sled_config);
+
+        // sled's defaults are kept when the options are unset
+        let db_opts = DbOpts {
+            mode: Some(config::DbMode::Fast),
+            ..Default::default()
+        };
+        let sled_config: sled::Config = (&db_opts).into();
+        assert_eq!(sled_config.flush_every_ms, Some(500));
+        assert!(!sled_config.temporary);
+    }
+
+    #[test]
+    fn test_db_move_entry() {
+        let db = Database::new_temporary().unwrap();
+        let pending = db.open_tree(DbTrees::Custom("pending")).unwrap();
+        let committed = db.open_tree(DbTrees::Custom("committed")).unwrap();
+        pending
+            .insert(&TestData {
+                key: "key1".to_string(),
+                foo: "foo1".to_string(),
+            })
+            .unwrap();
+        let bytes = pending.get("key1").unwrap().unwrap();
+        assert!(db
+            .move_entry(
+                DbTrees::Custom("pending"),
+                DbTrees::Custom("committed"),
+                "key1"
+            )
+            .unwrap());
+        assert!(!pending.contains_key("key1").unwrap());
+        assert_eq!(committed.get("key1").unwrap().unwrap(), bytes);
+        // moving a missing key leaves both trees untouched
+        assert!(!db
+            .move_entry(
+                DbTrees::Custom("pending"),
+                DbTrees::Custom("committed"),
+                "key1"
+            )
+            .unwrap());
+        assert!(pending.is_empty());
+        assert_eq!(committed.len(), 1);
+    }
+
+    #[test]
+    fn test_db_insert_if_absent() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("insert_if_absent")).unwrap();
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let threads = (0..2)
+            .map(|i| {
+                let tree = tree.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    let inserted = tree
+                        .insert_if_absent(&TestData {
+                            key: "dedup".to_string(),
+                            foo: format!("thread{}", i),
+                        })
+                        .unwrap();
+                    (inserted, format!("thread{}", i))
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        let winners = results
+            .iter()
+            .filter(|(inserted, _)| *inserted)
+            .collect::<Vec<_>>();
+        assert_eq!(winners.len(), 1);
+        let stored: TestData = tree.deserialize("dedup").unwrap();
+        assert_eq!(stored.foo, winners[0].1);
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_db_tree_exists() {
+        let db = Database::new_temporary().unwrap();
+        assert!(db.tree_exists(DbTrees::Default));
+        assert!(db.tree_names().is_empty());
+        assert!(!db.tree_exists(DbTrees::Custom("migrations")));
+        db.open_tree(DbTrees::Custom("migrations")).unwrap();
+        assert!(db.tree_exists(DbTrees::Custom("migrations")));
+        db.open_tree(DbTrees::Custom("data")).unwrap();
+        let mut names = db.tree_names();
+        names.sort();
+        assert_eq!(names, vec!["data".to_string(), "migrations".to_string()]);
+    }
+
+    #[test]
+    fn test_db_tree_batch() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("tree_batch")).unwrap();
+        let other = db.open_tree(DbTrees::Custom("other")).unwrap();
+        tree.insert(&TestData {
+            key: "key0".to_string(),
+            foo: "foo0".to_string(),
+        })
+        .unwrap();
+        let mut batch = tree.batch();
+        for i in 1..3 {
+            batch
+                .insert(&TestData {
+                    key: format!("key{}", i),
+                    foo: format!("foo{}", i),
+                })
+                .unwrap();
+        }
+        batch.remove("key0").unwrap();
+        assert_eq!(batch.count(), 3);
+        // nothing is written until the batch is committed
+        assert_eq!(tree.len(), 1);
+        batch.commit().unwrap();
+        assert_eq!(
+            tree.keys().collect::<sled::Result<Vec<_>>>().unwrap(),
+            vec![IVec::from("key1"), IVec::from("key2")]
+        );
+        let value: TestData = tree.deserialize("key2").unwrap();
+        assert_eq!(value.foo, "foo2".to_string());
+        assert!(other.is_empty());
+    }
+
+    #[test]
+    fn test_db_verify() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("verify")).unwrap();
+        for i in 0..3 {
+            tree.insert(&TestData {
+                key: format!("key{}", i),
+                foo: format!("foo{}", i),
+            })
+            .unwrap();
+        }
+        assert!(tree.verify::<TestData>().unwrap().is_ok());
+
+        tree.tree.insert("key1a", vec![255_u8; 7]).unwrap();
+        let report = tree.verify::<TestData>().unwrap();
+        assert_eq!(
+            report,
+            VerifyReport {
+                total: 4,
+                decoded: 3,
+                failed_keys: vec![IVec::from("key1a")],
+            }
+        );
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn test_db_insert_all() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("insert_all")).unwrap();
+        let values = (0..1000)
+            .map(|i| TestData {
+                key: format!("key{}", i),
+                foo: format!("foo{}", i),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tree.insert_all(values).unwrap(), 1000);
+        assert_eq!(tree.len(), 1000);
+        assert_eq!(tree.insert_all(Vec::<TestData>::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_db_precompressed_codec() {
+        // run length encodes the borsh bytes, standing in for a real compressor
+        struct RunLengthCodec;
+
+        impl<T: BorshSerialize + BorshDeserialize> Codec<T> for RunLengthCodec {
+            fn encode(value: &T) -> Result<Vec<u8>> {
+                let bytes = BorshCodec::encode(value)?;
+                let mut encoded = Vec::new();
+                for chunk in bytes.chunk_by(|a, b| a == b) {
+                    for run in chunk.chunks(u8::MAX as usize) {
+                        encoded.push(run.len() as u8);
+                        encoded.push(run[0]);
+                    }
+                }
+                Ok(encoded)
+            }
+            fn decode(bytes: &[u8]) -> Result<T> {
+                let decoded = bytes
+                    .chunks(2)
+                    .flat_map(|run| std::iter::repeat_n(run[1], run[0] as usize))
+                    .collect::<Vec<_>>();
+                BorshCodec::decode(&decoded)
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let compressed = db.open_tree(DbTrees::Custom("compressed")).unwrap();
+        let uncompressed = db.open_tree(DbTrees::Custom("uncompressed")).unwrap();
+        let data = TestData {
+            key: "key1".to_string(),
+            foo: "o".repeat(1000),
+        };
+        compressed.insert_with::<RunLengthCodec, _>(&data).unwrap();
+        uncompressed.insert(&data).unwrap();
+        assert!(
+            compressed.get("key1").unwrap().unwrap().len()
+                < uncompressed.get("key1").unwrap().unwrap().len()
+        );
+        let value: TestData = compressed
+            .get_deserialize_with::<RunLengthCodec, _, _>("key1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(value.foo, data.foo);
+    }
+
+    #[test]
+    fn test_db_push() {
+        let db = Database::new_temporary().unwrap();
+        let first = db.generate_id().unwrap();
+        assert!(db.generate_id().unwrap() > first);
+
+        let tree = db.open_tree(DbTrees::Custom("push")).unwrap();
+        let ids = ["first", "second", "third"]
+            .iter()
+            .map(|foo| {
+                tree.push(&TestData {
+                    key: String::new(),
+                    foo: foo.to_string(),
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(ids.windows(2).all(|ids| ids[0] < ids[1]));
+        let entries = tree
+            .iter_deserialize::<TestData>()
+            .map(|entry| {
+                let (key, value) = entry.unwrap();
+                (
+                    u64::from_be_bytes(key.as_ref().try_into().unwrap()),
+                    value.foo,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (ids[0], "first".to_string()),
+                (ids[1], "second".to_string()),
+                (ids[2], "third".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_db_batch_try_insert_all() {
+        struct MaybeKey(Option<&'static str>);
+
+        impl BorshSerialize for MaybeKey {
+            fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+                self.0.unwrap_or_default().serialize(writer)
+            }
+        }
+
+        impl DbKey for MaybeKey {
+            fn key(&self) -> anyhow::Result<Vec<u8>> {
+                match self.0 {
+                    Some(key) => Ok(key.as_bytes().to_vec()),
+                    None => Err(anyhow::anyhow!("missing key")),
+                }
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("try_insert_all")).unwrap();
+        let mut db_batch = DbBatch::new();
+        let (queued, errors) = db_batch.try_insert_all(vec![
+            MaybeKey(Some("key1")),
+            MaybeKey(None),
+            MaybeKey(Some("key2")),
+        ]);
+        assert_eq!(queued, 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert!(matches!(errors[0].1, SledUtilsError::Key(_)));
+        assert_eq!(db_batch.count(), 2);
+        tree.apply_batch(&mut db_batch).unwrap();
+        assert_eq!(
+            tree.keys().collect::<sled::Result<Vec<_>>>().unwrap(),
+            vec![IVec::from("key1"), IVec::from("key2")]
+        );
+    }
+
+    #[test]
+    fn test_db_iter_typed() {
+        #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+        struct EventKey {
+            timestamp: u64,
+            id: u32,
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("iter_typed")).unwrap();
+        let key = EventKey {
+            timestamp: 1,
+            id: 2,
+        };
+        let value = TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        };
+        tree.tree
+            .insert(borsh::to_vec(&key).unwrap(), borsh::to_vec(&value).unwrap())
+            .unwrap();
+        let entries = tree
+            .iter_typed::<EventKey, TestData>()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, key);
+        assert_eq!(entries[0].1.foo, "foo1");
+
+        tree.clear().unwrap();
+        tree.tree
+            .insert(vec![1_u8], borsh::to_vec(&value).unwrap())
+            .unwrap();
+        assert!(matches!(
+            tree.iter_typed::<EventKey, TestData>().next().unwrap(),
+            Err(SledUtilsError::DeserializeKey(_))
+        ));
+
+        tree.clear().unwrap();
+        tree.tree
+            .insert(borsh::to_vec(&key).unwrap(), vec![1_u8])
+            .unwrap();
+        assert!(matches!(
+            tree.iter_typed::<EventKey, TestData>().next().unwrap(),
+            Err(SledUtilsError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_db_stats() {
+        let db = Database::new_temporary().unwrap();
+        let first = db.open_tree(DbTrees::Custom("first")).unwrap();
+        let second = db.open_tree(DbTrees::Custom("second")).unwrap();
+        for key in ["key1", "key2"] {
+            first.tree.insert(key, "value").unwrap();
+        }
+        second.tree.insert("key3", "value").unwrap();
+
+        let stats = db.stats_with_entries().unwrap();
+        assert_eq!(stats.tree_count, 2);
+        assert_eq!(stats.total_entries, Some(3));
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.tree_count, 2);
+        assert_eq!(stats.total_entries, None);
+    }
+
+    #[test]
+    fn test_db_apply_batch_durable() {
+        let db_opts = DbOpts {
+            path: temp_db_path("apply_batch_durable"),
+            ..Default::default()
+        };
+        {
+            let db = Database::new(&db_opts).unwrap();
+            let tree = db.open_tree(DbTrees::Custom("durable")).unwrap();
+            let mut db_batch = DbBatch::new();
+            db_batch
+                .insert(&TestData {
+                    key: "key1".to_string(),
+                    foo: "foo1".to_string(),
+                })
+                .unwrap();
+            tree.apply_batch_durable(&mut db_batch).unwrap();
+        }
+
+        let db = reopen(&db_opts);
+        let tree = db.open_tree(DbTrees::Custom("durable")).unwrap();
+        let value: TestData = tree.deserialize("key1").unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+        drop(tree);
+        db.destroy();
+        drop(db);
+        remove_dir_all(&db_opts.path).unwrap();
+    }
+
+    #[test]
+    fn test_db_update() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("update")).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        tree.update("total", |total: Option<u64>| {
+                            Some(total.unwrap_or_default() + 1)
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+        let total: u64 = tree.deserialize("total").unwrap();
+        assert_eq!(total, 400);
+
+        assert_eq!(
+            tree.update("total", |total: Option<u64>| total.map(|total| total * 2))
+                .unwrap(),
+            Some(800)
+        );
+        assert_eq!(tree.update("total", |_: Option<u64>| None).unwrap(), None);
+        assert!(!tree.contains_key("total").unwrap());
+    }
+
+    #[test]
+    fn test_db_open_trees() {
+        let db = Database::new_temporary().unwrap();
+        let trees = [
+            DbTrees::Custom("first"),
+            DbTrees::Custom("second"),
+            DbTrees::Custom("third"),
+        ];
+        let handles = db.open_trees(&trees).unwrap();
+        assert_eq!(handles.len(), 3);
+        let mut db_batch = DbBatch::new();
+        db_batch
+            .insert(&TestData {
+                key: "key1".to_string(),
+                foo: "foo1".to_string(),
+            })
+            .unwrap();
+        handles[1].apply_batch(&mut db_batch).unwrap();
+        assert_eq!(handles[0].len(), 0);
+        assert_eq!(handles[1].len(), 1);
+        assert_eq!(handles[2].len(), 0);
+
+        let named = db.open_trees_named(&trees).unwrap();
+        assert_eq!(named.len(), 3);
+        assert!(named["second"].contains_key("key1").unwrap());
+        assert!(!named["third"].contains_key("key1").unwrap());
+    }
+
+    #[test]
+    fn test_db_drop_tree() {
+        let db = Database::new_temporary().unwrap();
+        let dropped = db.open_tree(DbTrees::Custom("dropped")).unwrap();
+        let kept = db.open_tree(DbTrees::Custom("kept")).unwrap();
+        dropped.tree.insert("key1", "value").unwrap();
+        kept.tree.insert("key1", "value").unwrap();
+
+        assert!(db.drop_tree(DbTrees::Custom("dropped")).unwrap());
+        assert!(!db.tree_exists(DbTrees::Custom("dropped")));
+        assert!(!db.drop_tree(DbTrees::Custom("dropped")).unwrap());
+        assert!(db.tree_exists(DbTrees::Custom("kept")));
+        assert!(kept.contains_key("key1").unwrap());
+        let reopened = db.open_tree(DbTrees::Custom("dropped")).unwrap();
+        assert!(reopened.is_empty());
+    }
+
+    #[test]
+    fn test_db_list_paginated() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("paginated")).unwrap();
+        for key in ["key1", "key2", "key3", "key4", "key5"] {
+            tree.tree.insert(key, "value").unwrap();
+        }
+        let mut pages = Vec::new();
+        let mut start_after = None;
+        loop {
+            let page = db
+                .list_paginated(DbTrees::Custom("paginated"), start_after, 2)
+                .unwrap();
+            if page.is_empty() {
+                break;
+            }
+            start_after = page.last().map(|(key, _)| key.clone());
+            pages.push(page.into_iter().map(|(key, _)| key).collect::<Vec<_>>());
+        }
+        assert_eq!(
+            pages,
+            vec![
+                vec![IVec::from("key1"), IVec::from("key2")],
+                vec![IVec::from("key3"), IVec::from("key4")],
+                vec![IVec::from("key5")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_db_sequence() {
+        let db_opts = DbOpts {
+            path: temp_db_path("sequence"),
+            ..Default::default()
+        };
+        {
+            let db = Database::new(&db_opts).unwrap();
+            let sequence = db.sequence("orders").unwrap();
+            let mut values = std::thread::scope(|scope| {
+                let handles = (0..4)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            (0..25)
+                                .map(|_| sequence.next().unwrap())
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap())
+                    .collect::<Vec<_>>()
+            });
+            values.sort_unstable();
+            assert_eq!(values, (0..100).collect::<Vec<_>>());
+            // sequences are independent of each other
+            assert_eq!(db.sequence("invoices").unwrap().next().unwrap(), 0);
+            db.flush().unwrap();
+        }
+
+        let db = reopen(&db_opts);
+        assert_eq!(db.sequence("orders").unwrap().next().unwrap(), 100);
+        db.destroy();
+        drop(db);
+        remove_dir_all(&db_opts.path).unwrap();
+    }
+
+    #[test]
+    fn test_db_find() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("find")).unwrap();
+        for (key, foo) in [("key1", "foo1"), ("key2", "foo2"), ("key3", "foo2")] {
+            tree.insert(&TestData {
+                key: key.to_string(),
+                foo: foo.to_string(),
+            })
+            .unwrap();
+        }
+        let (key, value) = tree
+            .find(|value: &TestData| value.foo == "foo2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(key, IVec::from("key2"));
+        assert_eq!(value.key, "key2".to_string());
+        assert!(tree
+            .find(|value: &TestData| value.foo == "missing")
+            .unwrap()
+            .is_none());
+
+        tree.tree.insert("key0", vec![1_u8]).unwrap();
+        assert!(matches!(
+            tree.find(|value: &TestData| value.foo == "foo2"),
+            Err(SledUtilsError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_db_opts_validate() {
+        let db_opts = DbOpts {
+            system_page_cache: Some(1),
+            temporary: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Database::new(&db_opts),
+            Err(SledUtilsError::InvalidConfig(_))
+        ));
+        let db_opts = DbOpts {
+            compression_factor: Some(-1),
+            ..db_opts
+        };
+        assert!(matches!(
+            db_opts.validate(),
+            Err(SledUtilsError::InvalidConfig(_))
+        ));
+
+        let db_opts = DbOpts {
+            path: "test_opts_validate.db".to_string(),
+            system_page_cache: Some(64 * 1024 * 1024),
+            temporary: true,
+            ..Default::default()
+        };
+        assert!(Database::new(&db_opts).is_ok());
+
+        let db_opts = DbOpts {
+            mode: Some(config::DbMode::Fast),
+            ..Default::default()
+        };
+        let sled_config: sled::Config = (&db_opts).into();
+        assert_eq!(
+            sled_config.cache_capacity,
+            config::DEFAULT_FAST_CACHE_CAPACITY
+        );
+    }
+
+    #[test]
+    fn test_db_replace() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("replace")).unwrap();
+        let value = |foo: &str| TestData {
+            key: "key1".to_string(),
+            foo: foo.to_string(),
+        };
+        assert!(tree.replace(&value("foo1")).unwrap().is_none());
+        let previous = tree.replace(&value("foo2")).unwrap().unwrap();
+        assert_eq!(previous.foo, "foo1".to_string());
+        let current: TestData = tree.deserialize("key1").unwrap();
+        assert_eq!(current.foo, "foo2".to_string());
+
+        tree.tree.insert("key1", vec![1_u8]).unwrap();
+        assert!(matches!(
+            tree.replace(&value("foo3")),
+            Err(SledUtilsError::Deserialize(_))
+        ));
+    }
+
+    #[test]
+    fn test_db_batch_counts() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("batch_counts")).unwrap();
+        let mut db_batch = DbBatch::new();
+        assert!(db_batch.is_empty());
+        for key in ["key1", "key2"] {
+            db_batch
+                .insert(&TestData {
+                    key: key.to_string(),
+                    foo: "foo".to_string(),
+                })
+                .unwrap();
+        }
+        db_batch.remove("key3").unwrap();
+        assert_eq!(db_batch.insert_count(), 2);
+        assert_eq!(db_batch.remove_count(), 1);
+        assert_eq!(db_batch.count(), 3);
+        assert!(!db_batch.is_empty());
+        tree.apply_batch(&mut db_batch).unwrap();
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_db_transform_tree() {
+        #[derive(BorshSerialize, BorshDeserialize)]
+        struct V1 {
+            id: u32,
+            name: String,
+        }
+        #[derive(BorshSerialize, BorshDeserialize)]
+        struct V2 {
+            id: u32,
+            name: String,
+            active: bool,
+        }
+        impl DbKey for V2 {
+            fn key(&self) -> anyhow::Result<Vec<u8>> {
+                Ok(self.id.to_be_bytes().to_vec())
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let v1 = db.open_tree(DbTrees::Custom("v1")).unwrap();
+        for id in 0..3_u32 {
+            v1.tree
+                .insert(
+                    id.to_be_bytes(),
+                    borsh::to_vec(&V1 {
+                        id,
+                        name: format!("name{}", id),
+                    })
+                    .unwrap(),
+                )
+                .unwrap();
+        }
+        let migrate = |value: V1| {
+            Ok(V2 {
+                id: value.id,
+                name: value.name,
+                active: true,
+            })
+        };
+        assert_eq!(
+            db.transform_tree(DbTrees::Custom("v1"), DbTrees::Custom("v2"), migrate)
+                .unwrap(),
+            3
+        );
+        let v2 = db.open_tree(DbTrees::Custom("v2")).unwrap();
+        let value: V2 = v2.deserialize(2_u32.to_be_bytes()).unwrap();
+        assert_eq!(value.name, "name2".to_string());
+        assert!(value.active);
+        assert_eq!(v1.len(), 3);
+
+        v1.tree.insert(3_u32.to_be_bytes(), vec![1_u8]).unwrap();
+        match db.transform_tree(DbTrees::Custom("v1"), DbTrees::Custom("v3"), migrate) {
+            Err(SledUtilsError::Transform(key, err)) => {
+                assert_eq!(key, IVec::from(&3_u32.to_be_bytes()));
+                assert!(matches!(*err, SledUtilsError::Deserialize(_)));
+            }
+            _ => panic!("expected transform error"),
+        }
+        assert!(db.open_tree(DbTrees::Custom("v3")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_db_get_raw() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("get_raw")).unwrap();
+        let blob = (0..=255_u8).collect::<Vec<_>>();
+        tree.tree.insert("blob", blob.clone()).unwrap();
+        assert_eq!(
+            tree.get_raw("blob", |bytes| bytes.to_vec()).unwrap(),
+            Some(blob)
+        );
+        assert_eq!(
+            tree.get_raw("blob", |bytes| bytes.len()).unwrap(),
+            Some(256)
+        );
+        assert_eq!(tree.get_raw("missing", |bytes| bytes.len()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_db_opts_from_env() {
+        for (name, value) in [
+            ("PATH", "env.db"),
+            ("MODE", "Low_Space"),
+            ("CACHE", "1048576"),
+            ("COMPRESSION_FACTOR", "3"),
+            ("FLUSH_EVERY_MS", "100"),
+            ("DEBUG", "true"),
+            ("READ_ONLY", "true"),
+            ("TEMPORARY", "true"),
+        ] {
+            std::env::set_var(format!("SLED_UTILS_FULL_{}", name), value);
+        }
+        let db_opts = DbOpts::from_env("SLED_UTILS_FULL").unwrap();
+        assert_eq!(
+            db_opts,
+            DbOpts {
+                path: "env.db".to_string(),
+                mode: Some(config::DbMode::LowSpace),
+                system_page_cache: Some(1048576),
+                compression_factor: Some(3),
+                flush_every_ms: Some(100),
+                debug: true,
+                read_only: true,
+                temporary: true,
+                ..Default::default()
+            }
+        );
+
+        std::env::set_var("SLED_UTILS_PARTIAL_PATH", "partial.db");
+        assert_eq!(
+            DbOpts::from_env("SLED_UTILS_PARTIAL").unwrap(),
+            DbOpts::from_path("partial.db")
+        );
+
+        std::env::set_var("SLED_UTILS_INVALID_MODE", "slow");
+        assert!(matches!(
+            DbOpts::from_env("SLED_UTILS_INVALID"),
+            Err(SledUtilsError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_db_list_values_reporting() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("reporting")).unwrap();
+        for key in ["key1", "key2"] {
+            tree.tree.insert(key, "value").unwrap();
+        }
+        let mut errors = Vec::new();
+        let values = db
+            .list_values_reporting(DbTrees::Custom("reporting"), |err| errors.push(err))
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(
+            values,
+            db.list_values(DbTrees::Custom("reporting")).unwrap()
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_db_from_sled_config() {
+        let config = sled::Config::new()
+            .temporary(true)
+            .idgen_persist_interval(100);
+        let db = Database::from_sled_config(config).unwrap();
+        assert!(!db.is_read_only());
+        let tree = db.open_tree(DbTrees::Custom("from_sled_config")).unwrap();
+        tree.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        let value: TestData = tree.deserialize("key1").unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+    }
+
+    #[test]
+    fn test_db_iter_string_keys() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("string_keys")).unwrap();
+        for key in ["key1", "key2"] {
+            tree.insert(&TestData {
+                key: key.to_string(),
+                foo: "foo".to_string(),
+            })
+            .unwrap();
+        }
+        let keys = tree
+            .iter_string_keys()
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(keys, vec!["key1".to_string(), "key2".to_string()]);
+        let values = db
+            .list_string_keyed::<TestData>(DbTrees::Custom("string_keys"))
+            .unwrap();
+        assert_eq!(values[1].0, "key2".to_string());
+        assert_eq!(values[1].1.key, "key2".to_string());
+
+        tree.tree.insert(vec![0xff_u8, 0xfe], "value").unwrap();
+        let entries = tree.iter_string_keys().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert!(matches!(entries[2], Err(SledUtilsError::DeserializeKey(_))));
+        assert!(db
+            .list_string_keyed::<TestData>(DbTrees::Custom("string_keys"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_db_shutdown() {
+        let db_opts = DbOpts {
+            path: temp_db_path("shutdown"),
+            ..Default::default()
+        };
+        {
+            let db = Database::new(&db_opts).unwrap();
+            for name in ["first", "second"] {
+                db.open_tree(DbTrees::Custom(name))
+                    .unwrap()
+                    .insert(&TestData {
+                        key: "key1".to_string(),
+                        foo: name.to_string(),
+                    })
+                    .unwrap();
+            }
+            db.shutdown().unwrap();
+        }
+
+        let db = reopen(&db_opts);
+        for name in ["first", "second"] {
+            let value: TestData = db
+                .open_tree(DbTrees::Custom(name))
+                .unwrap()
+                .deserialize("key1")
+                .unwrap();
+            assert_eq!(value.foo, name.to_string());
+        }
+        db.destroy();
+        drop(db);
+        remove_dir_all(&db_opts.path).unwrap();
+    }
+
+    #[test]
+    fn test_db_apply_batch_checked() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("checked")).unwrap();
+        tree.tree.insert("key3", "value").unwrap();
+        let mut db_batch = DbBatch::new();
+        for key in ["key1", "key2"] {
+            db_batch
+                .insert(&TestData {
+                    key: key.to_string(),
+                    foo: "foo".to_string(),
+                })
+                .unwrap();
+        }
+        db_batch.remove("key3").unwrap();
+        db_batch.remove("missing").unwrap();
+        let replay = db_batch.clone();
+
+        assert_eq!(
+            tree.apply_batch_checked(&mut db_batch).unwrap(),
+            BatchApplyReport {
+                changed: 3,
+                unchanged: 1,
+            }
+        );
+        assert_eq!(tree.len(), 2);
+        assert_eq!(
+            tree.apply_batch_checked(&mut replay.clone()).unwrap(),
+            BatchApplyReport {
+                changed: 0,
+                unchanged: 4,
+            }
+        );
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_db_key() {
+        #[derive(types::DbKey)]
+        #[db_key(field = "key")]
+        struct Single {
+            key: String,
+            _foo: String,
+        }
+        #[derive(types::DbKey)]
+        #[db_key(field = "user_id", field = "name")]
+        struct Composite<'a> {
+            name: &'a str,
+            user_id: u64,
+        }
+
+        let single = Single {
+            key: "key1".to_string(),
+            _foo: "foo1".to_string(),
+        };
+        assert_eq!(single.key().unwrap(), b"key1".to_vec());
+        let composite = Composite {
+            name: "alice",
+            user_id: 7,
+        };
+        assert_eq!(
+            composite.key().unwrap(),
+            CompositeKey::new()
+                .u64(7)
+                .separator()
+                .bytes("alice")
+                .into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_db_typed_tree() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db
+            .open_typed_tree::<&str, TestData>(DbTrees::Custom("typed"))
+            .unwrap();
+        let value = |foo: &str| TestData {
+            key: "key1".to_string(),
+            foo: foo.to_string(),
+        };
+        assert!(tree.insert(&"key1", &value("foo1")).unwrap().is_none());
+        let previous = tree.insert(&"key1", &value("foo2")).unwrap().unwrap();
+        assert_eq!(previous.foo, "foo1".to_string());
+        assert_eq!(tree.get(&"key1").unwrap().unwrap().foo, "foo2".to_string());
+        assert!(tree.get(&"missing").unwrap().is_none());
+        let entries = tree.iter().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, IVec::from("key1"));
+        assert_eq!(
+            tree.remove(&"key1").unwrap().unwrap().foo,
+            "foo2".to_string()
+        );
+        assert!(tree.remove(&"key1").unwrap().is_none());
+        assert!(tree.inner().is_empty());
+    }
+
+    #[test]
+    fn test_db_remove_deserialize() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("remove_deserialize")).unwrap();
+        tree.insert(&TestData {
+            key: "key1".to_string(),
+            foo: "foo1".to_string(),
+        })
+        .unwrap();
+        let removed: TestData = tree.remove_deserialize("key1").unwrap().unwrap();
+        assert_eq!(removed.foo, "foo1".to_string());
+        assert!(tree
+            .remove_deserialize::<_, TestData>("key1")
+            .unwrap()
+            .is_none());
+        assert!(!tree.contains_key("key1").unwrap());
+    }
+
+    #[test]
+    fn test_db_batch_remove_value() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("batch_remove_value")).unwrap();
+        let data = |key: &str| TestData {
+            key: key.to_string(),
+            foo: "foo".to_string(),
+        };
+        tree.insert(&data("key1")).unwrap();
+        tree.insert(&data("key2")).unwrap();
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data("key3")).unwrap();
+        db_batch.remove_value(&data("key1")).unwrap();
+        db_batch.remove("key2").unwrap();
+        assert_eq!(db_batch.insert_count(), 1);
+        assert_eq!(db_batch.remove_count(), 2);
+        tree.apply_batch(&mut db_batch).unwrap();
+        assert_eq!(
+            tree.keys().collect::<sled::Result<Vec<_>>>().unwrap(),
+            vec![IVec::from("key3")]
+        );
+
+        let mut tree_batch = tree.batch();
+        tree_batch.remove_value(&data("key3")).unwrap();
+        tree_batch.commit().unwrap();
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_db_scan_page() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("scan_page")).unwrap();
+        for key in ["key1", "key2", "key3", "key4"] {
+            tree.tree.insert(key, "value").unwrap();
+        }
+        let page = db.scan_page(DbTrees::Custom("scan_page"), None, 3).unwrap();
+        assert_eq!(page.entries.len(), 3);
+        assert_eq!(page.cursor, Some(IVec::from("key3")));
+        let page = db
+            .scan_page(DbTrees::Custom("scan_page"), page.cursor, 3)
+            .unwrap();
+        assert_eq!(
+            page.entries,
+            vec![(IVec::from("key4"), IVec::from("value"))]
+        );
+        assert_eq!(page.cursor, None);
+        // a page ending exactly on the last key has no cursor
+        let page = db.scan_page(DbTrees::Custom("scan_page"), None, 4).unwrap();
+        assert_eq!(page.entries.len(), 4);
+        assert_eq!(page.cursor, None);
+        // an empty page can't advance the cursor
+        assert!(matches!(
+            db.scan_page(DbTrees::Custom("scan_page"), None, 0),
+            Err(SledUtilsError::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_db_stream() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("stream")).unwrap();
+        // more entries than the stream buffers, so the reader has to wait on the consumer
+        for i in 0..stream::STREAM_BUFFER as u64 * 2 {
+            tree.tree
+                .insert(i.to_be_bytes(), borsh::to_vec(&i).unwrap())
+                .unwrap();
+        }
+        let mut stream = tree.stream::<u64>();
+        let values = block_on(async {
+            let mut values = Vec::new();
+            while let Some(entry) = stream.next().await {
+                values.push(entry.unwrap().1);
+            }
+            values
+        });
+        assert_eq!(
+            values,
+            (0..stream::STREAM_BUFFER as u64 * 2).collect::<Vec<_>>()
+        );
+
+        tree.tree
+            .insert(u64::MAX.to_be_bytes(), vec![1_u8])
+            .unwrap();
+        let mut stream = tree.stream::<u64>();
+        let last = block_on(async {
+            let mut last = None;
+            while let Some(entry) = stream.next().await {
+                last = Some(entry);
+            }
+            last
+        });
+        assert!(matches!(last, Some(Err(SledUtilsError::Deserialize(_)))));
+        // dropping a stream part way through stops its reader
+        let mut stream = tree.stream::<u64>();
+        assert!(block_on(stream.next()).is_some());
+        drop(stream);
+    }
+
+    #[test]
+    fn test_db_async_database() {
+        let db = asynchronous::AsyncDatabase::new(Database::new_temporary().unwrap());
+        block_on(async {
+            let tree = db.open_tree(DbTrees::Custom("async")).await.unwrap();
+            tree.insert(TestData {
+                key: "key1".to_string(),
+                foo: "foo1".to_string(),
+            })
+            .await
+            .unwrap();
+            let mut db_batch = DbBatch::new();
+            db_batch
+                .insert(&TestData {
+                    key: "key2".to_string(),
+                    foo: "foo2".to_string(),
+                })
+                .unwrap();
+            tree.apply_batch(db_batch).await.unwrap();
+            assert!(tree.get("key1").await.unwrap().is_some());
+            assert!(tree.get("missing").await.unwrap().is_none());
+            let value: TestData = tree.get_deserialize("key2").await.unwrap().unwrap();
+            assert_eq!(value.foo, "foo2".to_string());
+            tree.flush_async().await.unwrap();
+            db.flush_async().await.unwrap();
+            assert_eq!(tree.inner().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_db_typed_transaction() {
+        use transaction::TypedTransactionalTree;
+
+        let db = Database::new_temporary().unwrap();
+        let trees = [DbTrees::Custom("pending"), DbTrees::Custom("done")];
+        db.open_tree(trees[0])
+            .unwrap()
+            .insert(&TestData {
+                key: "key1".to_string(),
+                foo: "foo1".to_string(),
+            })
+            .unwrap();
+        let moved = db
+            .transaction(&trees, |tx_trees| {
+                let value: Option<TestData> = tx_trees[0].remove_deserialize("key1")?;
+                if let Some(value) = &value {
+                    tx_trees[1].insert_value(value)?;
+                }
+                Ok(value.is_some())
+            })
+            .map_err(SledUtilsError::from)
+            .unwrap();
+        assert!(moved);
+        let done = db.open_tree(trees[1]).unwrap();
+        let value: TestData = done.deserialize("key1").unwrap();
+        assert_eq!(value.foo, "foo1".to_string());
+        assert!(db.open_tree(trees[0]).unwrap().is_empty());
+
+        // a value which fails to be deserialized aborts with the error
+        done.tree.insert("key2", vec![1_u8]).unwrap();
+        let result = db
+            .transaction(&trees, |tx_trees| {
+                tx_trees[1].insert("key3", "value")?;
+                tx_trees[1].get_deserialize::<_, TestData>("key2")
+            })
+            .map_err(SledUtilsError::from);
+        assert!(matches!(result, Err(SledUtilsError::Deserialize(_))));
+        assert!(!done.contains_key("key3").unwrap());
+    }
+
+    #[test]
+    fn test_db_compare_and_swap_typed() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("cas_typed")).unwrap();
+        assert_eq!(
+            tree.compare_and_swap_typed("version", None, Some(&1_u64))
+                .unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            tree.compare_and_swap_typed("version", None, Some(&2_u64))
+                .unwrap(),
+            Err(CasError { current: Some(1) })
+        );
+        assert_eq!(
+            tree.compare_and_swap_typed("version", Some(&1_u64), Some(&2_u64))
+                .unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            tree.compare_and_swap_typed("version", Some(&2_u64), None)
+                .unwrap(),
+            Ok(())
+        );
+        assert_eq!(
+            tree.compare_and_swap_typed("version", Some(&2_u64), Some(&3_u64))
+                .unwrap(),
+            Err(CasError { current: None })
+        );
+    }
+
+    #[test]
+    fn test_db_fetch_and_update() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("fetch_and_update")).unwrap();
+        let increment = |total: Option<u64>| Some(total.unwrap_or_default() + 1);
+        assert_eq!(tree.fetch_and_update("total", increment).unwrap(), None);
+        assert_eq!(tree.fetch_and_update("total", increment).unwrap(), Some(1));
+        assert_eq!(tree.update("total", increment).unwrap(), Some(3));
+        assert_eq!(
+            tree.fetch_and_update("total", |_: Option<u64>| None)
+                .unwrap(),
+            Some(3)
+        );
+        assert!(!tree.contains_key("total").unwrap());
+    }
+
+    #[test]
+    fn test_db_set_merge() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("set_merge")).unwrap();
+        tree.set_merge(|_key, old: Option<Vec<String>>, delta: Vec<String>| {
+            let mut merged = old.unwrap_or_default();
+            merged.extend(delta);
+            Some(merged)
+        });
+        assert_eq!(
+            tree.merge_typed("tags", &vec!["a".to_string()]).unwrap(),
+            Some(vec!["a".to_string()])
+        );
+        assert_eq!(
+            tree.merge_typed("tags", &vec!["b".to_string(), "c".to_string()])
+                .unwrap(),
+            Some(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+        );
+        // a delta which fails to be deserialized leaves the stored value unchanged
+        tree.merge("tags", [1_u8]).unwrap();
+        let tags: Vec<String> = tree.deserialize("tags").unwrap();
+        assert_eq!(tags.len(), 3);
+    }
+
+    #[test]
+    fn test_db_get_or_insert_with() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("get_or_insert_with")).unwrap();
+        let calls = std::sync::atomic::AtomicU64::new(0);
+        let values = std::thread::scope(|scope| {
+            let handles = (0..8_u64)
+                .map(|i| {
+                    let tree = &tree;
+                    let calls = &calls;
+                    scope.spawn(move || {
+                        tree.get_or_insert_with("config", || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            i
+                        })
+                        .unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<u64>>()
+        });
+        assert!(values.iter().all(|value| *value == values[0]));
+        assert_eq!(tree.deserialize::<_, u64>("config").unwrap(), values[0]);
+        let before = calls.load(Ordering::SeqCst);
+        assert!(before >= 1);
+        // an existing value never invokes f
+        assert_eq!(
+            tree.get_or_insert_with("config", || -> u64 { unreachable!() })
+                .unwrap(),
+            values[0]
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), before);
+    }
+
+    #[test]
+    fn test_db_get_many() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("get_many")).unwrap();
+        for key in ["key1", "key3"] {
+            tree.insert(&TestData {
+                key: key.to_string(),
+                foo: format!("foo_{}", key),
+            })
+            .unwrap();
+        }
+        let values = tree
+            .get_many::<_, TestData>(&["key3", "key2", "key1"])
+            .unwrap()
+            .into_iter()
+            .map(|value| value.map(|value| value.foo))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                Some("foo_key3".to_string()),
+                None,
+                Some("foo_key1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_db_event_bus() {
+        let db = Database::new_temporary().unwrap();
+        let bus = db.event_bus::<u64>(DbTrees::Custom("event_bus")).unwrap();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        let tree = db.open_tree(DbTrees::Custom("event_bus")).unwrap();
+        tree.tree
+            .insert("key1", borsh::to_vec(&1_u64).unwrap())
+            .unwrap();
+        tree.tree.insert("key2", vec![1_u8]).unwrap();
+        tree.tree.remove("key1").unwrap();
+        for receiver in [&mut first, &mut second] {
+            assert_eq!(
+                block_on(receiver.recv()),
+                Some((IVec::from("key1"), Some(1)))
+            );
+            // the undecodable insert of key2 is skipped
+            assert_eq!(block_on(receiver.recv()), Some((IVec::from("key1"), None)));
+        }
+        drop(bus);
+        assert_eq!(block_on(first.recv()), None);
+    }
+
+    #[test]
+    fn test_db_cdc() {
+        use cdc::CdcOp;
+
+        let db = Database::new(&DbOpts {
+            path: std::env::temp_dir()
+                .join(format!("sled-utils-cdc-{}", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+            cdc: true,
+            temporary: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let tree = db.open_tree(DbTrees::Custom("cdc")).unwrap();
+        let data = |key: &str| TestData {
+            key: key.to_string(),
+            foo: "foo".to_string(),
+        };
+        tree.insert(&data("key1")).unwrap();
+        tree.remove("key1").unwrap();
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data("key2")).unwrap();
+        db_batch.remove("key3").unwrap();
+        tree.apply_batch(&mut db_batch).unwrap();
+
+        let records = db.cdc_tail(None, 10).unwrap();
+        let ops = records
+            .iter()
+            .map(|(_, record)| (record.op, record.key.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            vec![
+                (CdcOp::Insert, b"key1".to_vec()),
+                (CdcOp::Remove, b"key1".to_vec()),
+                (CdcOp::Insert, b"key2".to_vec()),
+                (CdcOp::Remove, b"key3".to_vec()),
+            ]
+        );
+        assert!(records
+            .iter()
+            .all(|(_, record)| record.tree == b"cdc".to_vec()));
+        assert_eq!(
+            records[0].1.value,
+            Some(borsh::to_vec(&data("key1")).unwrap())
+        );
+        assert!(records.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(tree.len(), 1);
+
+        let tail = db.cdc_tail(Some(records[1].0), 10).unwrap();
+        assert_eq!(tail, records[2..].to_vec());
+        assert_eq!(db.cdc_truncate(records[1].0).unwrap(), 2);
+        assert_eq!(db.cdc_tail(None, 10).unwrap(), records[2..].to_vec());
+
+        // without cdc nothing is recorded
+        let db = Database::new_temporary().unwrap();
+        db.open_tree(DbTrees::Custom("cdc"))
+            .unwrap()
+            .insert(&data("key1"))
+            .unwrap();
+        assert!(db.cdc_tail(None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_db_cdc_write_paths() {
+        let mut db = Database::new(&DbOpts {
+            path: temp_db_path("cdc_write_paths"),
+            cdc: true,
+            temporary: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let data = |key: &str, foo: &str| TestData {
+            key: key.to_string(),
+            foo: foo.to_string(),
+        };
+        let tree = db.open_tree(DbTrees::Custom("cdc")).unwrap();
+        tree.compare_and_swap("swapped", None, Some(&1_u64))
+            .unwrap()
+            .unwrap();
+        tree.insert_if_absent(&data("absent", "foo")).unwrap();
+        tree.get_or_insert(&data("inserted", "foo")).unwrap();
+        tree.update("updated", |value: Option<u64>| {
+            Some(value.unwrap_or_default() + 1)
+        })
+        .unwrap();
+        tree.get_or_insert_with("lazy", || 2_u64).unwrap();
+        tree.set_merge_operator(counter_merge);
+        db.open_tree(DbTrees::Custom("cdc"))
+            .unwrap()
+            .increment("merged", 3)
+            .unwrap();
+        tree.insert(&data("prefix1", "foo")).unwrap();
+        tree.remove_prefix("prefix").unwrap();
+        tree.insert_with_ttl(&data("expiring", "foo"), Duration::ZERO)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(db.purge_expired().unwrap(), 1);
+        tree.insert(&data("moved", "foo")).unwrap();
+        db.move_entry(DbTrees::Custom("cdc"), DbTrees::Custom("moved"), "moved")
+            .unwrap();
+        let cleared = db.open_tree(DbTrees::Custom("cleared")).unwrap();
+        cleared.insert(&data("key", "foo")).unwrap();
+        cleared.clear().unwrap();
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data("batched", "foo")).unwrap();
+        db.apply_batch(&mut db_batch).unwrap();
+        Arc::get_mut(&mut db)
+            .unwrap()
+            .insert(&data("default", "foo"))
+            .unwrap();
+
+        db.counters().unwrap().incr("counter", 1).unwrap();
+        db.sequence("sequence").unwrap().next().unwrap();
+        let queue = db.open_queue::<u64>(DbTrees::Custom("queue")).unwrap();
+        queue.push(&1).unwrap();
+        queue.push(&2).unwrap();
+        queue.pop_front().unwrap();
+        let deque = db.open_deque::<u64>(DbTrees::Custom("deque")).unwrap();
+        deque.push_front(&1).unwrap();
+        deque.push_back(&2).unwrap();
+        deque.pop_back().unwrap();
+        let priorities = db
+            .open_priority_queue::<u64>(DbTrees::Custom("priorities"))
+            .unwrap();
+        let key = priorities.push(1, &1).unwrap();
+        priorities.push(2, &2).unwrap();
+        priorities.reprioritize(&key, 3).unwrap();
+        priorities.pop_lowest().unwrap();
+        let set = db
+            .open_sorted_set(DbTrees::Custom("members"), DbTrees::Custom("scores"))
+            .unwrap();
+        set.zadd("one", 1.0).unwrap();
+        set.zadd("two", 2.0).unwrap();
+        set.zadd("one", 3.0).unwrap();
+        set.zrem("two").unwrap();
+        let users = db
+            .unique_index::<TestData>(DbTrees::Custom("users"), DbTrees::Custom("users_by_foo"))
+            .unwrap();
+        users.insert(&data("key1", "foo1")).unwrap();
+        users.insert(&data("key2", "foo2")).unwrap();
+        users.remove("key1").unwrap();
+        let multimap = db
+            .open_multimap::<u64>(DbTrees::Custom("multimap"))
+            .unwrap();
+        multimap.insert("key", &1).unwrap();
+        multimap.insert("key", &2).unwrap();
+        multimap.remove_value("key", &1).unwrap();
+        db.open_tree(DbTrees::Custom("migrated"))
+            .unwrap()
+            .insert(&data("key", "foo"))
+            .unwrap();
+        Migrator::new()
+            .register(DbTrees::Custom("migrated"), move |_: &[u8]| {
+                serialize_value(&data("key", "bar"))
+            })
+            .run(&db)
+            .unwrap();
+
+        // replaying the log reproduces every tree written through the wrappers
+        let replica = Database::new_temporary().unwrap();
+        for (_, record) in db.cdc_tail(None, usize::MAX).unwrap() {
+            let tree = replica.inner().open_tree(&record.tree).unwrap();
+            match record.value {
+                Some(value) => tree.insert(record.key, value).unwrap(),
+                None => tree.remove(record.key).unwrap(),
+            };
+        }
+        for name in db.inner().tree_names() {
+            if name == CDC_TREE_ID.as_bytes() || ttl::is_ttl_tree(&name) {
+                continue;
+            }
+            let entries = |db: &Arc<Database>| {
+                db.inner()
+                    .open_tree(&name)
+                    .unwrap()
+                    .iter()
+                    .collect::<sled::Result<Vec<_>>>()
+                    .unwrap()
+            };
+            assert_eq!(entries(&replica), entries(&db), "tree {:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_db_ttl() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("ttl")).unwrap();
+        let data = |key: &str| TestData {
+            key: key.to_string(),
+            foo: "foo".to_string(),
+        };
+        tree.insert(&data("kept")).unwrap();
+        tree.insert_with_ttl(&data("expired"), Duration::ZERO)
+            .unwrap();
+        tree.insert_with_ttl(&data("pending"), Duration::ZERO)
+            .unwrap();
+        // replacing the deadline leaves a stale index entry which mustn't purge it
+        tree.insert_with_ttl(&data("pending"), Duration::from_secs(3600))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+
+        assert_eq!(db.purge_expired().unwrap(), 1);
+        assert!(!tree.contains_key("expired").unwrap());
+        assert!(tree.contains_key("pending").unwrap());
+        assert!(tree.contains_key("kept").unwrap());
+        let expirations = db
+            .open_tree(DbTrees::Custom(ttl::EXPIRATIONS_TREE_ID))
+            .unwrap();
+        // index keys too short to hold a deadline are dropped
+        expirations.tree.insert([0_u8; 3], vec![]).unwrap();
+        assert_eq!(db.purge_expired().unwrap(), 0);
+        assert_eq!(expirations.len(), 1);
+    }
+
+    #[test]
+    fn test_db_ttl_tracked_trees() {
+        let db_opts = DbOpts {
+            path: temp_db_path("ttl_tracked_trees"),
+            ..Default::default()
+        };
+        let db = Database::new(&db_opts).unwrap();
+        let data = TestData {
+            key: "key".to_string(),
+            foo: "foo".to_string(),
+        };
+        let expiring = db.open_tree(DbTrees::Custom("expiring")).unwrap();
+        let plain = db.open_tree(DbTrees::Custom("plain")).unwrap();
+        expiring
+            .insert_with_ttl(&data, Duration::from_secs(3600))
+            .unwrap();
+        // only the tree given a deadline writes through transactions
+        assert!(expiring.is_tracked());
+        assert!(db
+            .open_tree(DbTrees::Custom("expiring"))
+            .unwrap()
+            .is_tracked());
+        assert!(!plain.is_tracked());
+        plain.insert(&data).unwrap();
+        db.flush().unwrap();
+        drop((expiring, plain, db));
+
+        // the trees holding deadlines are found again once reopened
+        let db = reopen(&db_opts);
+        assert!(db
+            .open_tree(DbTrees::Custom("expiring"))
+            .unwrap()
+            .is_tracked());
+        assert!(!db.open_tree(DbTrees::Custom("plain")).unwrap().is_tracked());
+        assert!(db.drop_tree(DbTrees::Custom("expiring")).unwrap());
+        assert!(!db
+            .open_tree(DbTrees::Custom("expiring"))
+            .unwrap()
+            .is_tracked());
+        db.destroy();
+        drop(db);
+        remove_dir_all(&db_opts.path).unwrap();
+    }
+
+    #[test]
+    fn test_db_ttl_cleared_by_writes() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("ttl")).unwrap();
+        let data = |key: &str, foo: &str| TestData {
+            key: key.to_string(),
+            foo: foo.to_string(),
+        };
+        for key in ["inserted", "removed", "batched", "prefixed"] {
+            tree.insert_with_ttl(&data(key, "old"), Duration::ZERO)
+                .unwrap();
+        }
+        // overwriting the key without a ttl keeps the new value for good
+        tree.insert(&data("inserted", "new")).unwrap();
+        tree.remove("removed").unwrap();
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data("batched", "new")).unwrap();
+        tree.apply_batch(&mut db_batch).unwrap();
+        tree.remove_prefix("prefixed").unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+
+        assert_eq!(
+            tree.deserialize::<_, TestData>("inserted").unwrap().foo,
+            "new"
+        );
+        assert_eq!(
+            tree.deserialize::<_, TestData>("batched").unwrap().foo,
+            "new"
+        );
+        let deadlines = db
+            .open_tree(DbTrees::Custom(ttl::DEADLINES_TREE_ID))
+            .unwrap();
+        let expirations = db
+            .open_tree(DbTrees::Custom(ttl::EXPIRATIONS_TREE_ID))
+            .unwrap();
+        assert!(deadlines.is_empty());
+        assert!(expirations.is_empty());
+        assert_eq!(db.purge_expired().unwrap(), 0);
+        assert_eq!(tree.len(), 2);
+
+        // clearing or dropping a tree drops the deadlines of its entries too
+        tree.insert_with_ttl(&data("cleared", "old"), Duration::from_secs(3600))
+            .unwrap();
+        tree.clear().unwrap();
+        assert!(deadlines.is_empty());
+        tree.insert_with_ttl(&data("dropped", "old"), Duration::from_secs(3600))
+            .unwrap();
+        assert!(db.drop_tree(DbTrees::Custom("ttl")).unwrap());
+        assert!(deadlines.is_empty());
+        assert!(expirations.is_empty());
+    }
+
+    #[test]
+    fn test_db_expiration_worker() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("ttl")).unwrap();
+        tree.insert_with_ttl(
+            &TestData {
+                key: "key".to_string(),
+                foo: "foo".to_string(),
+            },
+            Duration::ZERO,
+        )
+        .unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let worker = db.spawn_expiration_worker(Duration::from_millis(5), move |purged| {
+            let _ = sender.send(purged.unwrap());
+        });
+        let mut reclaimed = 0;
+        while reclaimed == 0 {
+            reclaimed = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(reclaimed, 1);
+        assert!(!tree.contains_key("key").unwrap());
+        drop(worker);
+        // dropping the worker joins its thread, dropping the callback
+        receiver.try_iter().for_each(drop);
+        assert_eq!(
+            receiver.try_recv(),
+            Err(std::sync::mpsc::TryRecvError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_db_ttl_expired_reads() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("ttl")).unwrap();
+        // opened before any ttl exists, so it relies on the shared deadlines
+        let other = db.open_tree(DbTrees::Custom("ttl")).unwrap();
+        let data = |key: &str| TestData {
+            key: key.to_string(),
+            foo: "foo".to_string(),
+        };
+        tree.insert_with_ttl(&data("expired"), Duration::ZERO)
+            .unwrap();
+        tree.insert_with_ttl(&data("pending"), Duration::from_secs(3600))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+
+        for tree in [&tree, &other] {
+            assert!(tree.get("expired").unwrap().is_none());
+            assert!(!tree.contains_key("expired").unwrap());
+            assert!(tree
+                .get_deserialize::<_, TestData>("expired")
+                .unwrap()
+                .is_none());
+            assert!(matches!(
+                tree.deserialize::<_, TestData>("expired"),
+                Err(SledUtilsError::KeyNotFound(_))
+            ));
+            assert_eq!(
+                tree.deserialize::<_, TestData>("pending").unwrap().key,
+                "pending"
+            );
+        }
+        // the entry remains stored until it's purged
+        assert_eq!(tree.len(), 2);
+        assert_eq!(db.purge_expired().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_db_ttl_expired_writes() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("ttl")).unwrap();
+        let data = |key: &str, foo: &str| TestData {
+            key: key.to_string(),
+            foo: foo.to_string(),
+        };
+        for key in [
+            "absent", "checked", "inserted", "lazy", "removed", "replaced", "updated",
+        ] {
+            tree.insert_with_ttl(&data(key, "old"), Duration::ZERO)
+                .unwrap();
+        }
+        let queue = db.open_queue::<u64>(DbTrees::Custom("queue")).unwrap();
+        queue.push(&1).unwrap();
+        // an undecodable entry sorting before every id
+        queue
+            .inner()
+            .insert_with_ttl(&data("\0", "old"), Duration::ZERO)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+
+        // the ends of a tree and peeks skip expired values
+        assert!(tree.first().unwrap().is_none());
+        assert!(tree.last().unwrap().is_none());
+        assert_eq!(queue.peek().unwrap(), Some(1));
+        // writes don't return expired previous values
+        assert!(tree.replace(&data("replaced", "new")).unwrap().is_none());
+        assert!(tree
+            .remove_deserialize::<_, TestData>("removed")
+            .unwrap()
+            .is_none());
+        let mut db_batch = DbBatch::new();
+        db_batch.insert(&data("checked", "old")).unwrap();
+        let report = tree.apply_batch_checked(&mut db_batch).unwrap();
+        assert_eq!((report.changed, report.unchanged), (1, 0));
+
+        // conditional writes treat expired values as absent
+        assert!(tree.insert_if_absent(&data("absent", "new")).unwrap());
+        assert_eq!(
+            tree.get_or_insert(&data("inserted", "new")).unwrap().foo,
+            "new"
+        );
+        assert_eq!(
+            tree.get_or_insert_with("lazy", || data("lazy", "new"))
+                .unwrap()
+                .foo,
+            "new"
+        );
+        assert!(tree
+            .fetch_and_update("updated", |value: Option<TestData>| {
+                assert!(value.is_none());
+                Some(data("updated", "new"))
+            })
+            .unwrap()
+            .is_none());
+        // pops skip expired values
+        assert_eq!(queue.pop_front().unwrap(), Some(1));
+        assert_eq!(queue.pop_front().unwrap(), None);
+
+        // the values written by them no longer expire
+        assert_eq!(db.purge_expired().unwrap(), 1);
+        assert_eq!(tree.len(), 6);
+        for key in ["absent", "inserted", "lazy", "replaced", "updated"] {
+            assert_eq!(tree.deserialize::<_, TestData>(key).unwrap().foo, "new");
+        }
+    }
+
+    #[test]
+    fn test_db_unique_index() {
+        let db = Database::new_temporary().unwrap();
+        let users = db
+            .unique_index::<TestData>(DbTrees::Custom("users"), DbTrees::Custom("users_by_foo"))
+            .unwrap();
+        let data = |key: &str, foo: &str| TestData {
+            key: key.to_string(),
+            foo: foo.to_string(),
+        };
+        assert!(users.insert(&data("key1", "foo1")).unwrap().is_none());
+        assert!(matches!(
+            users.insert(&data("key2", "foo1")),
+            Err(SledUtilsError::DuplicateKey(key)) if key == "foo1"
+        ));
+        assert!(users.get("key2").unwrap().is_none());
+
+        // changing the unique key of a value releases its previous one
+        let previous = users.insert(&data("key1", "foo2")).unwrap().unwrap();
+        assert_eq!(previous.foo, "foo1");
+        users.insert(&data("key2", "foo1")).unwrap();
+        assert_eq!(
+            users.get_by_unique_key("foo2").unwrap().unwrap().key,
+            "key1"
+        );
+        assert_eq!(
+            users.get_by_unique_key("foo1").unwrap().unwrap().key,
+            "key2"
+        );
+
+        users.remove("key1").unwrap().unwrap();
+        assert!(users.get_by_unique_key("foo2").unwrap().is_none());
+        assert_eq!(users.index().len(), 1);
+        users.insert(&data("key3", "foo2")).unwrap();
+    }
+
+    #[test]
+    fn test_key_builder() {
+        use types::{KeyBuilder, KeyReader};
+
+        let key = |name: &[u8], id: u64| KeyBuilder::new().bytes(name).u64(id).into_bytes();
+        // a shorter leading segment sorts first whatever the segments after it
+        let mut keys = vec![
+            key(b"ab", 0),
+            key(b"a", u64::MAX),
+            key(b"a\0", 1),
+            key(b"", 5),
+        ];
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                key(b"", 5),
+                key(b"a", u64::MAX),
+                key(b"a\0", 1),
+                key(b"ab", 0)
+            ]
+        );
+
+        let mut reader = KeyReader::new(&keys[2]);
+        assert_eq!(reader.bytes().unwrap(), b"a\0".to_vec());
+        assert_eq!(reader.u64().unwrap(), 1);
+        reader.finish().unwrap();
+
+        let truncated = &keys[2][..keys[2].len() - 1];
+        let mut reader = KeyReader::new(truncated);
+        reader.bytes().unwrap();
+        assert!(matches!(
+            reader.u64(),
+            Err(SledUtilsError::DeserializeKey(_))
+        ));
+        assert!(matches!(
+            KeyReader::new(b"unterminated").str(),
+            Err(SledUtilsError::DeserializeKey(_))
+        ));
+        assert!(KeyReader::new(&keys[0]).finish().is_err());
+    }
+
+    #[test]
+    fn test_numeric_keys() {
+        use types::keys;
+
+        let ints = [i64::MIN, -300, -1, 0, 1, 256, i64::MAX];
+        let encoded = ints.map(keys::encode_i64);
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(encoded.map(keys::decode_i64), ints);
+
+        let floats = [
+            f64::NEG_INFINITY,
+            -1.5e300,
+            -2.0,
+            -0.5,
+            -0.0,
+            0.0,
+            1e-300,
+            3.25,
+            f64::INFINITY,
+        ];
+        let encoded = floats.map(keys::encode_f64);
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(encoded.map(keys::decode_f64), floats);
+
+        assert!(keys::encode_u64(255) < keys::encode_u64(256));
+        assert_eq!(keys::decode_u64(keys::encode_u64(42)), 42);
+
+        // range scans over encoded keys visit values in numeric order
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("prices")).unwrap();
+        for value in [2.5, -7.0, 0.0, -0.25, 10.0] {
+            tree.tree.insert(keys::encode_f64(value), vec![]).unwrap();
+        }
+        let in_range = tree
+            .range(keys::encode_f64(-1.0)..keys::encode_f64(5.0))
+            .keys()
+            .map(|key| keys::decode_f64(key.unwrap().as_ref().try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(in_range, vec![-0.25, 0.0, 2.5]);
+    }
+
+    #[test]
+    fn test_db_range_by_time() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("events")).unwrap();
+        for (timestamp_ms, suffix) in [
+            (1_000, "b"),
+            (999, "a"),
+            (1_000, "a"),
+            (2_000, "a"),
+            (2_001, "a"),
+        ] {
+            let value = TestData {
+                key: format!("{}{}", timestamp_ms, suffix),
+                foo: "foo".to_string(),
+            };
+            tree.tree
+                .insert(
+                    TimeKey::new(timestamp_ms, suffix).to_bytes(),
+                    borsh::to_vec(&value).unwrap(),
+                )
+                .unwrap();
+        }
+        let events = tree
+            .range_by_time::<TestData>(1_000, 2_001)
+            .map(|entry| {
+                let (key, value) = entry.unwrap();
+                (key.timestamp_ms, value.key)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (1_000, "1000a".to_string()),
+                (1_000, "1000b".to_string()),
+                (2_000, "2000a".to_string())
+            ]
+        );
+        assert_eq!(tree.range_by_time::<TestData>(2_001, 1_000).count(), 0);
+
+        let now = TimeKey::now("suffix");
+        assert_eq!(TimeKey::parse(&now.to_bytes()).unwrap(), now);
+        assert!(TimeKey::parse(b"short").is_err());
+    }
+
+    #[test]
+    fn test_db_insert_auto_key() {
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("auto")).unwrap();
+        let keys = (0..100)
+            .map(|i| {
+                tree.insert_auto_key(&TestData {
+                    key: i.to_string(),
+                    foo: "foo".to_string(),
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        // iterating the tree yields values in the order they were inserted
+        let values = tree
+            .iter_deserialize::<TestData>()
+            .map(|entry| entry.unwrap().1.key)
+            .collect::<Vec<_>>();
+        assert_eq!(values, (0..100).map(|i| i.to_string()).collect::<Vec<_>>());
+        let (key, _) = tree.first().unwrap().unwrap();
+        assert_eq!(
+            GeneratedKey::from_bytes(key.as_ref().try_into().unwrap()),
+            keys[0]
+        );
+
+        let key = keys[0];
+        assert!(key.timestamp_ms() <= ttl::now_ms());
+        let uuid = key.to_uuid_string();
+        assert_eq!(uuid.len(), 36);
+        // version 7 and the rfc 4122 variant
+        assert_eq!(&uuid[14..15], "7");
+        assert!(matches!(&uuid[19..20], "8" | "9" | "a" | "b"));
+        assert_eq!(key.to_ulid_string().len(), 26);
+        assert_eq!(
+            GeneratedKey::from_bytes([0xff; 16]).to_ulid_string(),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+    }
+
+    #[test]
+    fn test_db_counters() {
+        let db = Database::new_temporary().unwrap();
+        let counters = db.counters().unwrap();
+        assert_eq!(counters.get("hits").unwrap(), 0);
+        let writers = (0..4)
+            .map(|_| {
+                let counters = counters.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        counters.incr("hits", 2).unwrap();
+                        counters.decr("hits", 1).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        writers
+            .into_iter()
+            .for_each(|writer| writer.join().unwrap());
+        assert_eq!(counters.get("hits").unwrap(), 400);
+        // a new handle sets the merge operator again
+        assert_eq!(db.counters().unwrap().incr("hits", 1).unwrap(), 401);
+        assert_eq!(counters.reset("hits").unwrap(), 401);
+        assert_eq!(counters.get("hits").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_db_queue() {
+        let db = Database::new_temporary().unwrap();
+        let queue = db.open_queue::<u64>(DbTrees::Custom("jobs")).unwrap();
+        assert!(queue.pop_front().unwrap().is_none());
+        for job in 0..400 {
+            queue.push(&job).unwrap();
+        }
+        assert_eq!(queue.peek().unwrap(), Some(0));
+        assert_eq!(queue.len(), 400);
+        assert_eq!(queue.pop_front().unwrap(), Some(0));
+
+        let workers = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    while let Some(job) = queue.pop_front().unwrap() {
+                        popped.push(job);
+                    }
+                    popped
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut popped = Vec::new();
+        for worker in workers {
+            let jobs = worker.join().unwrap();
+            // each worker sees the jobs in the order they were pushed
+            assert!(jobs.windows(2).all(|pair| pair[0] < pair[1]));
+            popped.extend(jobs);
+        }
+        popped.sort_unstable();
+        assert_eq!(popped, (1..400).collect::<Vec<_>>());
+        assert!(queue.is_empty());
+
+        // a value which fails to be deserialized stays at the front
+        queue.inner().tree.insert([0; 8], vec![1]).unwrap();
+        assert!(matches!(
+            queue.pop_front(),
+            Err(SledUtilsError::Deserialize(_))
+        ));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_db_deque() {
+        let db = Database::new_temporary().unwrap();
+        let deque = db.open_deque::<u64>(DbTrees::Custom("deque")).unwrap();
+        assert!(deque.pop_back().unwrap().is_none());
+        deque.push_back(&2).unwrap();
+        deque.push_front(&1).unwrap();
+        deque.push_back(&3).unwrap();
+        deque.push_front(&0).unwrap();
+        assert_eq!(deque.len(), 4);
+        assert_eq!(deque.peek_front().unwrap(), Some(0));
+        assert_eq!(deque.peek_back().unwrap(), Some(3));
+        assert_eq!(deque.pop_back().unwrap(), Some(3));
+        assert_eq!(deque.pop_front().unwrap(), Some(0));
+        assert_eq!(deque.pop_front().unwrap(), Some(1));
+        assert_eq!(deque.pop_back().unwrap(), Some(2));
+        assert!(deque.is_empty());
+        // pushing past either end of the i64 keys fails instead of wrapping
+        deque
+            .inner()
+            .tree
+            .insert(
+                types::keys::encode_i64(i64::MAX),
+                serialize_value(&0_u64).unwrap(),
+            )
+            .unwrap();
+        deque
+            .inner()
+            .tree
+            .insert(
+                types::keys::encode_i64(i64::MIN),
+                serialize_value(&0_u64).unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            deque.push_back(&1),
+            Err(SledUtilsError::InvalidOperation(_))
+        ));
+        assert!(matches!(
+            deque.push_front(&1),
+            Err(SledUtilsError::InvalidOperation(_))
+        ));
+        deque.inner().tree.clear().unwrap();
+
+        let producers = (0..4)
+            .map(|producer| {
+                let deque = deque.clone();
+                std::thread::spawn(move || {
+                    for value in 0..50 {
+                        if producer % 2 == 0 {
+                            deque.push_front(&(producer * 100 + value)).unwrap();
+                        } else {
+                            deque.push_back(&(producer * 100 + value)).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        producers
+            .into_iter()
+            .for_each(|producer| producer.join().unwrap());
+        assert_eq!(deque.len(), 200);
+        let mut values = Vec::new();
+        while let Some(value) = deque.pop_front().unwrap() {
+            values.push(value);
+        }
+        values.sort_unstable();
+        let mut expected = (0..4)
+            .flat_map(|producer| (0..50).map(move |value| producer * 100 + value))
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_db_priority_queue() {
+        let db = Database::new_temporary().unwrap();
+        let queue = db
+            .open_priority_queue::<String>(DbTrees::Custom("priorities"))
+            .unwrap();
+        let push = |priority: i64, value: &str| queue.push(priority, &value.to_string()).unwrap();
+        push(5, "first five");
+        let low = push(-3, "low");
+        push(5, "second five");
+        push(10, "ten");
+        push(0, "zero");
+        let value = |entry: Option<(queue::PriorityKey, String)>| entry.map(|(_, value)| value);
+
+        assert_eq!(value(queue.peek_highest().unwrap()).as_deref(), Some("ten"));
+        assert_eq!(value(queue.pop_highest().unwrap()).as_deref(), Some("ten"));
+        // ties are popped in the order they were pushed from either end
+        assert_eq!(
+            value(queue.peek_highest().unwrap()).as_deref(),
+            Some("first five")
+        );
+        assert_eq!(
+            value(queue.pop_highest().unwrap()).as_deref(),
+            Some("first five")
+        );
+        let (key, lowest) = queue.pop_lowest().unwrap().unwrap();
+        assert_eq!((key, lowest.as_str()), (low, "low"));
+
+        // a popped value can't be reprioritized
+        assert!(queue.reprioritize(&low, 100).unwrap().is_none());
+        let (zero, _) = queue.peek_lowest().unwrap().unwrap();
+        let raised = queue.reprioritize(&zero, 100).unwrap().unwrap();
+        assert_eq!(raised.priority, 100);
+        assert_eq!(
+            queue.pop_highest().unwrap().unwrap(),
+            (raised, "zero".to_string())
+        );
+        assert_eq!(
+            value(queue.pop_highest().unwrap()).as_deref(),
+            Some("second five")
+        );
+        assert!(queue.is_empty());
+        assert!(queue.pop_lowest().unwrap().is_none());
+
+        for value in ["a", "b", "c"] {
+            push(7, value);
+        }
+        push(1, "d");
+        push(1, "e");
+        let popped = std::iter::from_fn(|| value(queue.pop_highest().unwrap())).collect::<Vec<_>>();
+        assert_eq!(popped, ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_db_sorted_set() {
+        let db = Database::new_temporary().unwrap();
+        let leaderboard = db
+            .open_sorted_set(DbTrees::Custom("vaults"), DbTrees::Custom("vaults_by_apy"))
+            .unwrap();
+        assert!(leaderboard.zadd("usdc", 4.5).unwrap());
+        assert!(leaderboard.zadd("sol", 12.0).unwrap());
+        assert!(leaderboard.zadd("ray", -1.0).unwrap());
+        assert!(leaderboard.zadd("btc", 4.5).unwrap());
+        // updating a score moves the member
+        assert!(!leaderboard.zadd("ray", 20.0).unwrap());
+        assert!(leaderboard.zadd("nan", f64::NAN).is_err());
+
+        assert_eq!(leaderboard.zscore("ray").unwrap(), Some(20.0));
+        assert_eq!(leaderboard.zscore("eth").unwrap(), None);
+        let members = |min, max| {
+            leaderboard
+                .zrange_by_score(min, max)
+                .unwrap()
+                .into_iter()
+                .map(|(member, _)| String::from_utf8(member.to_vec()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(members(0.0, 12.0), vec!["btc", "usdc", "sol"]);
+        assert_eq!(
+            members(f64::NEG_INFINITY, f64::INFINITY),
+            vec!["btc", "usdc", "sol", "ray"]
+        );
+        assert!(members(13.0, 19.0).is_empty());
+
+        assert_eq!(leaderboard.zrank("btc").unwrap(), Some(0));
+        assert_eq!(leaderboard.zrank("ray").unwrap(), Some(3));
+        assert_eq!(leaderboard.zrank("eth").unwrap(), None);
+
+        assert!(leaderboard.zrem("sol").unwrap());
+        assert!(!leaderboard.zrem("sol").unwrap());
+        assert_eq!(leaderboard.zrank("ray").unwrap(), Some(2));
+        assert_eq!(leaderboard.zcard(), 3);
+    }
+
+    #[test]
+    fn test_db_multimap() {
+        let db = Database::new_temporary().unwrap();
+        let tags = db.open_multimap::<String>(DbTrees::Custom("tags")).unwrap();
+        let tag = |tag: &str| tag.to_string();
+        assert!(tags.insert("vault", &tag("usdc")).unwrap());
+        assert!(tags.insert("vault", &tag("sol")).unwrap());
+        assert!(!tags.insert("vault", &tag("usdc")).unwrap());
+        // keys which extend another key don't share its values
+        assert!(tags.insert("vault2", &tag("ray")).unwrap());
+
+        let mut values = tags.get_all("vault").unwrap();
+        values.sort();
+        assert_eq!(values, vec![tag("sol"), tag("usdc")]);
+        assert_eq!(tags.get_all("vault2").unwrap(), vec![tag("ray")]);
+        assert!(tags.get_all("missing").unwrap().is_empty());
+        assert!(tags.contains("vault", &tag("sol")).unwrap());
+        assert!(!tags.contains("vault2", &tag("sol")).unwrap());
+
+        assert!(tags.remove_value("vault", &tag("sol")).unwrap());
+        assert!(!tags.remove_value("vault", &tag("sol")).unwrap());
+        assert_eq!(tags.get_all("vault").unwrap(), vec![tag("usdc")]);
+        assert_eq!(tags.remove_all("vault").unwrap(), 1);
+        assert_eq!(tags.inner().len(), 1);
+    }
+
+    #[test]
+    fn test_db_codec_generic_apis() {
+        // stores strings as their raw utf8 bytes
+        struct Utf8Codec;
+
+        impl Codec<String> for Utf8Codec {
+            fn encode(value: &String) -> Result<Vec<u8>> {
+                Ok(value.as_bytes().to_vec())
+            }
+            fn decode(bytes: &[u8]) -> Result<String> {
+                String::from_utf8(bytes.to_vec()).map_err(|err| {
+                    SledUtilsError::Deserialize(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        err,
+                    ))
+                })
+            }
+        }
+
+        impl Codec<TestData> for Utf8Codec {
+            fn encode(value: &TestData) -> Result<Vec<u8>> {
+                Ok(value.foo.as_bytes().to_vec())
+            }
+            fn decode(_: &[u8]) -> Result<TestData> {
+                unimplemented!("only used for inserting")
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let names = TypedTree::<String, String, Utf8Codec>::new(
+            db.open_tree(DbTrees::Custom("names")).unwrap(),
+        );
+        names
+            .insert(&"key1".to_string(), &"one".to_string())
+            .unwrap();
+        assert_eq!(
+            names.inner().get("key1").unwrap().unwrap(),
+            IVec::from("one")
+        );
+        assert_eq!(
+            names.get(&"key1".to_string()).unwrap().as_deref(),
+            Some("one")
+        );
+
+        let mut batch = DbBatch::new();
+        batch
+            .insert_with::<Utf8Codec, _>(&TestData {
+                key: "key2".to_string(),
+                foo: "two".to_string(),
+            })
+            .unwrap();
+        names.inner().apply_batch(&mut batch).unwrap();
+        assert_eq!(
+            names
+                .inner()
+                .deserialize_with::<Utf8Codec, _, String>("key2")
+                .unwrap(),
+            "two"
+        );
+        assert!(matches!(
+            names
+                .inner()
+                .deserialize_with::<Utf8Codec, _, String>("key3"),
+            Err(SledUtilsError::KeyNotFound(_))
+        ));
+        let values = names
+            .iter()
+            .map(|entry| entry.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_db_versioned() {
+        #[derive(BorshSerialize, BorshDeserialize)]
+        struct AccountV1 {
+            key: String,
+            balance: u32,
+        }
+
+        #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+        struct Account {
+            key: String,
+            balance: u64,
+            frozen: bool,
+        }
+
+        impl Versioned for AccountV1 {
+            const SCHEMA_VERSION: u32 = 1;
+        }
+
+        impl Versioned for Account {
+            const SCHEMA_VERSION: u32 = 2;
+
+            fn decode_version(schema_version: u32, payload: &[u8]) -> Result<Self> {
+                match schema_version {
+                    1 => {
+                        let old = deserialize_value::<AccountV1>(payload)?;
+                        Ok(Account {
+                            key: old.key,
+                            balance: old.balance.into(),
+                            frozen: false,
+                        })
+                    }
+                    _ => Err(SledUtilsError::InvalidOperation("unknown account version")),
+                }
+            }
+        }
+
+        impl DbKey for AccountV1 {
+            fn key(&self) -> anyhow::Result<Vec<u8>> {
+                Ok(self.key.as_bytes().to_vec())
+            }
+        }
+
+        impl DbKey for Account {
+            fn key(&self) -> anyhow::Result<Vec<u8>> {
+                Ok(self.key.as_bytes().to_vec())
+            }
+        }
+
+        let db = Database::new_temporary().unwrap();
+        let tree = db.open_tree(DbTrees::Custom("accounts")).unwrap();
+        tree.insert_versioned(&AccountV1 {
+            key: "old".to_string(),
+            balance: 7,
+        })
+        .unwrap();
+        let current = Account {
+            key: "new".to_string(),
+            balance: 1 << 40,
+            frozen: true,
+        };
+        tree.insert_versioned(&current).unwrap();
+
+        assert_eq!(
+            tree.deserialize_versioned::<_, Account>("old").unwrap(),
+            Account {
+                key: "old".to_string(),
+                balance: 7,
+                frozen: false,
+            }
+        );
+        assert_eq!(
+            tree.deserialize_versioned::<_, Account>("new").unwrap(),
+            current
+        );
+        // records written by a newer schema can't be read by an older one
+        assert!(matches!(
+            tree.deserialize_versioned::<_, AccountV1>("new"),
+            Err(SledUtilsError::Deserialize(_))
+        ));
+        let envelope: versioned::Envelope = tree.deserialize("old").unwrap();
+        assert_eq!(envelope.schema_version, 1);
+    }
+
+    #[test]
+    fn test_db_migrator() {
+        let db_opts = DbOpts {
+            path: temp_db_path("migrator"),
+            ..Default::default()
+        };
+        // widens a borsh u32 to a u64
+        let widen = |bytes: &[u8]| {
+            let value: u32 = deserialize_value(bytes)?;
+            serialize_value(&u64::from(value))
+        };
+        let double = |bytes: &[u8]| serialize_value(&(deserialize_value::<u64>(bytes)? * 2));
+
+        let db = Database::new(&db_opts).unwrap();
+        let balances = db.open_tree(DbTrees::Custom("balances")).unwrap();
+        balances
+            .tree
+            .insert("a", serialize_value(&3_u32).unwrap())
+            .unwrap();
+        balances
+            .tree
+            .insert("b", serialize_value(&5_u32).unwrap())
+            .unwrap();
+
+        let migrator = Migrator::new().register(DbTrees::Custom("balances"), widen);
+        assert_eq!(migrator.run(&db).unwrap(), 1);
+        // applied migrations aren't applied again
+        assert_eq!(migrator.run(&db).unwrap(), 0);
+        assert_eq!(balances.deserialize::<_, u64>("b").unwrap(), 5);
+
+        let migrator = migrator.register(DbTrees::Custom("balances"), double);
+        assert_eq!(migrator.run(&db).unwrap(), 1);
+        assert_eq!(balances.deserialize::<_, u64>("a").unwrap(), 6);
+        db.flush().unwrap();
+        drop(balances);
+        drop(db);
+
+        // a failing migration leaves the tree at the last level which succeeded
+        let failing = Migrator::new()
+            .register(DbTrees::Custom("balances"), widen)
+            .register(DbTrees::Custom("balances"), double)
+            .register(DbTrees::Custom("balances"), |_: &[u8]| {
+                Err(SledUtilsError::InvalidOperation("failed"))
+            });
+        let result = retry_locked(|| Database::new_with_migrator(&db_opts, &failing));
+        assert!(matches!(result, Err(SledUtilsError::Transform(key, _)) if key == "a"));
+        let db = reopen(&db_opts);
+        assert_eq!(
+            db.open_tree(DbTrees::Custom("balances"))
+                .unwrap()
+                .deserialize::<_, u64>("a")
+                .unwrap(),
+            6
+        );
+        // a migrator with fewer migrations than were applied is rejected
+        let older = Migrator::new().register(DbTrees::Custom("balances"), widen);
+        assert!(matches!(
+            older.run(&db),
+            Err(SledUtilsError::InvalidOperation(_))
+        ));
+        db.flush().unwrap();
+        drop(db);
+
+        // pending migrations can't be applied to a read only database
+        let read_only = DbOpts {
+            read_only: true,
+            ..db_opts.clone()
+        };
+        let db = retry_locked(|| Database::new(&read_only)).unwrap();
+        assert!(matches!(failing.run(&db), Err(SledUtilsError::ReadOnly)));
+        let applied = Migrator::new()
+            .register(DbTrees::Custom("balances"), widen)
+            .register(DbTrees::Custom("balances"), double);
+        assert_eq!(applied.run(&db).unwrap(), 0);
+        drop(db);
+        let db = reopen(&db_opts);
+        db.destroy();
+        drop(db);
+        remove_dir_all(&db_opts.path).unwrap();
     }
 }
@@ -0,0 +1,114 @@
+//! ordered schema migrations of the raw values stored in each tree, which are
+//! applied once each and tracked by a level persisted per tree
+
+use crate::error::{Result, SledUtilsError};
+use crate::transaction::transact;
+use crate::types::DbTrees;
+use crate::{Database, DbTree};
+use sled::IVec;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// the tree storing the number of migrations applied to each tree, as a
+/// borsh encoded u32 under the tree's name
+pub const MIGRATIONS_TREE_ID: &str = "__sled_utils_migrations";
+
+/// a migration converting the stored bytes of a value into its new bytes
+pub type Migration = Box<dyn Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Migrator holds the migrations of each tree, in the order they were
+/// registered. the level of a tree is the number of its migrations which have
+/// been applied, so migrations must only ever be appended, never reordered or
+/// removed, once released
+#[derive(Default)]
+pub struct Migrator {
+    migrations: BTreeMap<String, Vec<Migration>>,
+}
+
+impl Migrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// appends a migration of every value in tree, applied after the
+    /// migrations previously registered for it
+    pub fn register<F>(mut self, tree: DbTrees, migration: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.migrations
+            .entry(tree.str().into_owned())
+            .or_default()
+            .push(Box::new(migration));
+        self
+    }
+    /// applies the pending migrations of every tree, returning the number of
+    /// migrations applied. each migration rewrites its tree and advances the
+    /// tree's level in a single transaction, so a failing migration, which
+    /// returns `Transform` holding the offending key, leaves the tree at the
+    /// level of the last migration which succeeded. rewritten values keep their
+    /// deadlines and are recorded in the cdc tree when enabled.
+    ///
+    /// a tree is scanned before the transaction rewriting it, so run requires
+    /// exclusive access to the database: a value written to the tree while it
+    /// is migrated may be overwritten by its stale migrated value. prefer
+    /// `Database::new_with_migrator`, which runs before the database is shared.
+    /// `ReadOnly` is returned if the database is read only and a migration is
+    /// pending
+    pub fn run(&self, db: &Arc<Database>) -> Result<u64> {
+        if db.is_read_only() {
+            // nothing was ever migrated if the levels tree doesn't exist
+            let levels = match db.open_tree(DbTrees::Custom(MIGRATIONS_TREE_ID)) {
+                Ok(levels) => Some(levels),
+                Err(SledUtilsError::Sled(sled::Error::CollectionNotFound(_))) => None,
+                Err(err) => return Err(err),
+            };
+            for (name, migrations) in &self.migrations {
+                if level(levels.as_deref(), name, migrations)? < migrations.len() {
+                    return Err(SledUtilsError::ReadOnly);
+                }
+            }
+            return Ok(0);
+        }
+        let levels = db.open_tree(DbTrees::Custom(MIGRATIONS_TREE_ID))?;
+        let mut applied = 0;
+        for (name, migrations) in &self.migrations {
+            let level = level(Some(&levels), name, migrations)?;
+            let tree = db.open_named(name)?;
+            for (index, migration) in migrations.iter().enumerate().skip(level) {
+                let mut values = Vec::new();
+                for entry in tree.iter() {
+                    let (key, value) = entry?;
+                    let value = migration(&value)
+                        .map_err(|err| SledUtilsError::Transform(key.clone(), Box::new(err)))?;
+                    values.push((key, IVec::from(value)));
+                }
+                let level =
+                    borsh::to_vec(&(index as u32 + 1)).map_err(SledUtilsError::Serialize)?;
+                transact(&[&tree, &*levels], |tx_trees| {
+                    for (key, value) in &values {
+                        tx_trees[0].rewrite(key, value.clone())?;
+                    }
+                    tx_trees[1].insert(name, level.as_slice())?;
+                    Ok(())
+                })?;
+                applied += 1;
+            }
+        }
+        Ok(applied)
+    }
+}
+
+/// returns the number of migrations applied to the tree name, which fails if
+/// it exceeds the migrations registered for it
+fn level(levels: Option<&DbTree>, name: &str, migrations: &[Migration]) -> Result<usize> {
+    let level = match levels {
+        Some(levels) => levels.get_deserialize::<_, u32>(name)?.unwrap_or_default() as usize,
+        None => 0,
+    };
+    if level > migrations.len() {
+        return Err(SledUtilsError::InvalidOperation(
+            "tree was migrated by a newer version with more migrations",
+        ));
+    }
+    Ok(level)
+}
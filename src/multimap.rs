@@ -0,0 +1,102 @@
+//! maps where a single logical key holds any number of distinct values
+
+use crate::error::{Result, SledUtilsError};
+use crate::types::KeyBuilder;
+use crate::{deserialize_value, serialize_value, DbTree};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sled::IVec;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// MultiMap stores every value of a key as its own entry, keyed by the key as a
+/// terminated `KeyBuilder` segment followed by the big-endian 64 bit FNV-1a hash
+/// of the serialized value. FNV-1a is used as, unlike std's hasher, its output is
+/// fixed, which the stored keys depend on. the values of a key are returned in
+/// the order of their hashes
+pub struct MultiMap<T> {
+    tree: Arc<DbTree>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for MultiMap<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: BorshSerialize + BorshDeserialize> MultiMap<T> {
+    pub fn new(tree: Arc<DbTree>) -> Self {
+        Self {
+            tree,
+            _value: PhantomData,
+        }
+    }
+    /// adds value to the values of key, returning false if it was already one of
+    /// them. fails with `SledUtilsError::InvalidOperation` in the unlikely event
+    /// that another value of key has the same hash
+    pub fn insert<K: AsRef<[u8]>>(&self, key: K, value: &T) -> Result<bool> {
+        self.tree.ensure_writable()?;
+        let value = serialize_value(value)?;
+        match self.tree.cas(
+            &entry_key(key.as_ref(), &value),
+            None,
+            Some(IVec::from(value.as_slice())),
+        )? {
+            Ok(()) => Ok(true),
+            Err(err) if err.current.as_deref() == Some(value.as_slice()) => Ok(false),
+            Err(_) => Err(SledUtilsError::InvalidOperation(
+                "value hash collides with another value of the key",
+            )),
+        }
+    }
+    /// removes value from the values of key, returning false if it wasn't one of them
+    pub fn remove_value<K: AsRef<[u8]>>(&self, key: K, value: &T) -> Result<bool> {
+        self.tree.ensure_writable()?;
+        let value = serialize_value(value)?;
+        Ok(self
+            .tree
+            .cas(&entry_key(key.as_ref(), &value), Some(&value), None)?
+            .is_ok())
+    }
+    /// returns true if value is one of the values of key
+    pub fn contains<K: AsRef<[u8]>>(&self, key: K, value: &T) -> Result<bool> {
+        let value = serialize_value(value)?;
+        Ok(self
+            .tree
+            .get(entry_key(key.as_ref(), &value))?
+            .is_some_and(|stored| stored == value.as_slice()))
+    }
+    /// returns every value of key
+    pub fn get_all<K: AsRef<[u8]>>(&self, key: K) -> Result<Vec<T>> {
+        self.tree
+            .scan_prefix(key_prefix(key.as_ref()))
+            .values()
+            .map(|value| deserialize_value(&value?))
+            .collect()
+    }
+    /// removes every value of key, returning the number removed
+    pub fn remove_all<K: AsRef<[u8]>>(&self, key: K) -> Result<u64> {
+        self.tree.remove_prefix(key_prefix(key.as_ref()))
+    }
+    /// returns the untyped tree
+    pub fn inner(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+}
+
+fn key_prefix(key: &[u8]) -> Vec<u8> {
+    KeyBuilder::new().bytes(key).into_bytes()
+}
+
+fn entry_key(key: &[u8], value: &[u8]) -> Vec<u8> {
+    KeyBuilder::new().bytes(key).u64(fnv1a(value)).into_bytes()
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
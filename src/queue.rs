@@ -0,0 +1,333 @@
+//! persistent fifo, double ended and priority queues stored in a tree
+
+use crate::error::{Result, SledUtilsError};
+use crate::transaction::transact;
+use crate::types::keys;
+use crate::{deserialize_entry, deserialize_value, serialize_value, DbTree};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sled::{IVec, Tree};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Queue stores values of type T under the big-endian ids generated by
+/// `DbTree::push`, so they are popped in the order they were pushed. pops
+/// are atomic, so each value is handed to exactly one of any number of
+/// concurrent consumers
+pub struct Queue<T> {
+    tree: Arc<DbTree>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: BorshSerialize + BorshDeserialize> Queue<T> {
+    pub fn new(tree: Arc<DbTree>) -> Self {
+        Self {
+            tree,
+            _value: PhantomData,
+        }
+    }
+    /// appends value to the back of the queue, returning its id
+    pub fn push(&self, value: &T) -> Result<u64> {
+        self.tree.push(value)
+    }
+    /// removes and returns the value at the front of the queue. a value which
+    /// fails to be deserialized is left in the queue and its error returned
+    pub fn pop_front(&self) -> Result<Option<T>> {
+        Ok(pop(&self.tree, DbTree::first)?.map(|(_, value)| value))
+    }
+    /// returns the value at the front of the queue without removing it
+    pub fn peek(&self) -> Result<Option<T>> {
+        Ok(self.tree.first_deserialize()?.map(|(_, value)| value))
+    }
+    /// returns the number of values in the queue, which requires scanning them
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+    /// returns the untyped tree
+    pub fn inner(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+}
+
+/// Deque stores values of type T under order preserving i64 keys, which grow
+/// downwards from the front and upwards from the back of the deque. pushes and
+/// pops are atomic, so it can be shared by concurrent producers and consumers.
+/// pops compare and swap the entry they read rather than use sled's
+/// `pop_min`/`pop_max`, which remove an entry before it's decoded, so a value
+/// failing to deserialize would be lost, and can't skip expired entries or
+/// record the pop in the cdc tree
+pub struct Deque<T> {
+    tree: Arc<DbTree>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Deque<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: BorshSerialize + BorshDeserialize> Deque<T> {
+    pub fn new(tree: Arc<DbTree>) -> Self {
+        Self {
+            tree,
+            _value: PhantomData,
+        }
+    }
+    /// prepends value to the front of the deque, failing with
+    /// `InvalidOperation` once the front key reached i64::MIN
+    pub fn push_front(&self, value: &T) -> Result<()> {
+        self.push_at(value, Tree::first, |front| front.checked_sub(1))
+    }
+    /// appends value to the back of the deque, failing with `InvalidOperation`
+    /// once the back key reached i64::MAX
+    pub fn push_back(&self, value: &T) -> Result<()> {
+        self.push_at(value, Tree::last, |back| back.checked_add(1))
+    }
+    /// removes and returns the value at the front of the deque. a value which
+    /// fails to be deserialized is left in the deque and its error returned
+    pub fn pop_front(&self) -> Result<Option<T>> {
+        Ok(pop(&self.tree, DbTree::first)?.map(|(_, value)| value))
+    }
+    /// removes and returns the value at the back of the deque. a value which
+    /// fails to be deserialized is left in the deque and its error returned
+    pub fn pop_back(&self) -> Result<Option<T>> {
+        Ok(pop(&self.tree, DbTree::last)?.map(|(_, value)| value))
+    }
+    /// returns the value at the front of the deque without removing it
+    pub fn peek_front(&self) -> Result<Option<T>> {
+        Ok(self.tree.first_deserialize()?.map(|(_, value)| value))
+    }
+    /// returns the value at the back of the deque without removing it
+    pub fn peek_back(&self) -> Result<Option<T>> {
+        Ok(self.tree.last_deserialize()?.map(|(_, value)| value))
+    }
+    /// returns the number of values in the deque, which requires scanning them
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+    /// returns the untyped tree
+    pub fn inner(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+    /// inserts value under the key after the one of the entry returned by end,
+    /// retrying if another value is pushed to the same end concurrently
+    fn push_at<E, N>(&self, value: &T, end: E, next: N) -> Result<()>
+    where
+        E: Fn(&Tree) -> sled::Result<Option<(IVec, IVec)>>,
+        N: Fn(i64) -> Option<i64>,
+    {
+        self.tree.ensure_writable()?;
+        let value = IVec::from(serialize_value(value)?);
+        loop {
+            let key = match end(&self.tree.tree)? {
+                Some((key, _)) => {
+                    let end = keys::decode_i64(key.as_ref().try_into().map_err(|_| {
+                        SledUtilsError::InvalidOperation("deque key isn't an encoded i64")
+                    })?);
+                    next(end).ok_or(SledUtilsError::InvalidOperation(
+                        "deque is full at this end",
+                    ))?
+                }
+                None => 0,
+            };
+            if self
+                .tree
+                .cas(&keys::encode_i64(key), None, Some(value.clone()))?
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// PriorityKey identifies a value of a PriorityQueue, and is stored as its
+/// order preserving priority followed by its big-endian id, which breaks ties
+/// between equal priorities in the order values were pushed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PriorityKey {
+    pub priority: i64,
+    pub id: u64,
+}
+
+impl PriorityKey {
+    /// returns the key as stored in the tree
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&keys::encode_i64(self.priority));
+        key[8..].copy_from_slice(&self.id.to_be_bytes());
+        key
+    }
+    /// parses a key returned by `to_bytes`
+    pub fn parse(key: &[u8]) -> Result<Self> {
+        let mut reader = crate::types::KeyReader::new(key);
+        let priority = keys::decode_i64(reader.fixed()?);
+        let id = reader.u64()?;
+        reader.finish()?;
+        Ok(Self { priority, id })
+    }
+}
+
+/// PriorityQueue stores values of type T ordered by an i64 priority, from
+/// which the values of highest or lowest priority can be popped atomically
+pub struct PriorityQueue<T> {
+    tree: Arc<DbTree>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for PriorityQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: BorshSerialize + BorshDeserialize> PriorityQueue<T> {
+    pub fn new(tree: Arc<DbTree>) -> Self {
+        Self {
+            tree,
+            _value: PhantomData,
+        }
+    }
+    /// inserts value with the given priority, returning its key
+    pub fn push(&self, priority: i64, value: &T) -> Result<PriorityKey> {
+        self.tree.ensure_writable()?;
+        let key = PriorityKey {
+            priority,
+            id: self.tree.db.generate_id()?,
+        };
+        self.tree.write(
+            IVec::from(&key.to_bytes()),
+            Some(serialize_value(value)?.into()),
+        )?;
+        Ok(key)
+    }
+    /// removes and returns the value of highest priority, or of those the one
+    /// pushed first. a value which fails to be deserialized is left in the
+    /// queue and its error returned
+    pub fn pop_highest(&self) -> Result<Option<(PriorityKey, T)>> {
+        self.pop(highest)
+    }
+    /// removes and returns the value of lowest priority, or of those the one
+    /// pushed first. a value which fails to be deserialized is left in the
+    /// queue and its error returned
+    pub fn pop_lowest(&self) -> Result<Option<(PriorityKey, T)>> {
+        self.pop(DbTree::first)
+    }
+    /// returns the value `pop_highest` would remove, without removing it
+    pub fn peek_highest(&self) -> Result<Option<(PriorityKey, T)>> {
+        highest(&self.tree)
+            .transpose()
+            .map(deserialize_entry)
+            .transpose()?
+            .map(parse_entry)
+            .transpose()
+    }
+    /// returns the value of lowest priority without removing it
+    pub fn peek_lowest(&self) -> Result<Option<(PriorityKey, T)>> {
+        self.tree.first_deserialize()?.map(parse_entry).transpose()
+    }
+    /// atomically moves the value stored under key to the given priority,
+    /// returning its new key, or None if it was already popped
+    pub fn reprioritize(&self, key: &PriorityKey, priority: i64) -> Result<Option<PriorityKey>> {
+        self.tree.ensure_writable()?;
+        let new_key = PriorityKey { priority, ..*key };
+        transact(&[&self.tree], |tx_trees| {
+            match tx_trees[0].remove(key.to_bytes())? {
+                Some(value) => {
+                    tx_trees[0].insert(new_key.to_bytes(), value)?;
+                    Ok(Some(new_key))
+                }
+                None => Ok(None),
+            }
+        })
+    }
+    /// removes the value stored under key, returning it if it wasn't already popped
+    pub fn remove(&self, key: &PriorityKey) -> Result<Option<T>> {
+        self.tree.remove_deserialize(key.to_bytes())
+    }
+    /// returns the number of values in the queue, which requires scanning them
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+    /// returns the untyped tree
+    pub fn inner(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+    fn pop<F>(&self, read: F) -> Result<Option<(PriorityKey, T)>>
+    where
+        F: Fn(&DbTree) -> sled::Result<Option<(IVec, IVec)>>,
+    {
+        pop(&self.tree, read)?.map(parse_entry).transpose()
+    }
+}
+
+/// returns the entry of highest priority which hasn't expired, or of those the
+/// one pushed first, found by scanning the priority of the last entry forwards
+fn highest(tree: &DbTree) -> sled::Result<Option<(IVec, IVec)>> {
+    loop {
+        let (key, value) = match tree.last()? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        // a key which isn't a PriorityKey is left for parse_entry to reject
+        let priority = match key.get(..8) {
+            Some(priority) => priority,
+            None => return Ok(Some((key, value))),
+        };
+        for entry in tree.tree.scan_prefix(priority) {
+            let (key, value) = entry?;
+            if !tree.deadlines.is_expired(&tree.tree, &key)? {
+                return Ok(Some((key, value)));
+            }
+        }
+        // the last entry was removed concurrently, so look for the new one
+    }
+}
+
+fn parse_entry<T>((key, value): (IVec, T)) -> Result<(PriorityKey, T)> {
+    Ok((PriorityKey::parse(&key)?, value))
+}
+
+/// atomically removes and deserializes the unexpired entry of tree returned
+/// by read, calling read again if that entry was changed or removed concurrently
+pub(crate) fn pop<T, F>(tree: &DbTree, read: F) -> Result<Option<(IVec, T)>>
+where
+    T: BorshDeserialize,
+    F: Fn(&DbTree) -> sled::Result<Option<(IVec, IVec)>>,
+{
+    tree.ensure_writable()?;
+    loop {
+        let (key, value) = match read(tree)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let decoded = deserialize_value(&value)?;
+        if tree.cas(&key, Some(&value), None)?.is_ok() {
+            return Ok(Some((key, decoded)));
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! named, persistent and monotonic sequences stored in a dedicated tree
+
+use crate::error::Result;
+use crate::DbTree;
+use std::sync::Arc;
+
+/// the tree in which every sequence stores its state
+pub const SEQUENCES_TREE_ID: &str = "__sled_utils_sequences";
+
+/// Sequence hands out contiguous u64's starting at 0, which are never skipped
+/// or reused by concurrent callers. the number of values issued is stored as
+/// a borsh encoded u64 under the sequence's name, so it resumes from the last
+/// value after the database is reopened, subject to the database being flushed
+#[derive(Clone)]
+pub struct Sequence {
+    tree: Arc<DbTree>,
+    name: String,
+}
+
+impl Sequence {
+    pub(crate) fn new(tree: Arc<DbTree>, name: &str) -> Self {
+        Self {
+            tree,
+            name: name.to_string(),
+        }
+    }
+    /// atomically issues the next value of the sequence
+    pub fn next(&self) -> Result<u64> {
+        let issued = self
+            .tree
+            .update(&self.name, |issued: Option<u64>| {
+                Some(issued.unwrap_or_default() + 1)
+            })?
+            .unwrap_or_default();
+        Ok(issued - 1)
+    }
+    /// returns the name of the sequence
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
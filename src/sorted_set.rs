@@ -0,0 +1,111 @@
+//! redis style sorted sets of byte string members ordered by an f64 score
+
+use crate::error::{Result, SledUtilsError};
+use crate::transaction::transact;
+use crate::types::keys;
+use crate::DbTree;
+use sled::IVec;
+use std::sync::Arc;
+
+/// SortedSet stores the score of every member in a members tree, and indexes
+/// the members by score in a scores tree whose keys are the order preserving
+/// score followed by the member. both trees are updated in a single transaction
+#[derive(Clone)]
+pub struct SortedSet {
+    members: Arc<DbTree>,
+    scores: Arc<DbTree>,
+}
+
+impl SortedSet {
+    pub fn new(members: Arc<DbTree>, scores: Arc<DbTree>) -> Self {
+        Self { members, scores }
+    }
+    /// sets the score of member, returning true if it wasn't already in the set.
+    /// NaN scores are rejected as they have no place in the order
+    pub fn zadd<M: AsRef<[u8]>>(&self, member: M, score: f64) -> Result<bool> {
+        self.members.ensure_writable()?;
+        if score.is_nan() {
+            return Err(SledUtilsError::InvalidOperation(
+                "sorted set scores can't be NaN",
+            ));
+        }
+        let member = member.as_ref();
+        // adding 0.0 turns -0.0 into 0.0, which would otherwise sort apart
+        let score = keys::encode_f64(score + 0.0);
+        transact(&[&self.members, &self.scores], |tx_trees| {
+            let (tx_members, tx_scores) = (&tx_trees[0], &tx_trees[1]);
+            let previous = tx_members.insert(member, &score)?;
+            if let Some(previous) = &previous {
+                tx_scores.remove(score_key(previous, member))?;
+            }
+            tx_scores.insert(score_key(&score, member), IVec::default())?;
+            Ok(previous.is_none())
+        })
+    }
+    /// removes member from the set, returning true if it was in the set
+    pub fn zrem<M: AsRef<[u8]>>(&self, member: M) -> Result<bool> {
+        self.members.ensure_writable()?;
+        let member = member.as_ref();
+        transact(&[&self.members, &self.scores], |tx_trees| {
+            let (tx_members, tx_scores) = (&tx_trees[0], &tx_trees[1]);
+            let previous = tx_members.remove(member)?;
+            if let Some(previous) = &previous {
+                tx_scores.remove(score_key(previous, member))?;
+            }
+            Ok(previous.is_some())
+        })
+    }
+    /// returns the score of member, or None if it isn't in the set
+    pub fn zscore<M: AsRef<[u8]>>(&self, member: M) -> Result<Option<f64>> {
+        self.members
+            .get(member)?
+            .map(|score| decode_score(&score))
+            .transpose()
+    }
+    /// returns the members whose score is from min up to and including max,
+    /// along with their scores, in ascending order of score and then member
+    pub fn zrange_by_score(&self, min: f64, max: f64) -> Result<Vec<(IVec, f64)>> {
+        let mut members = Vec::new();
+        for key in self.scores.tree.range(keys::encode_f64(min + 0.0)..).keys() {
+            let key = key?;
+            let score = decode_score(&key[..8.min(key.len())])?;
+            if score > max {
+                break;
+            }
+            members.push((IVec::from(&key[8..]), score));
+        }
+        Ok(members)
+    }
+    /// returns the 0 based rank of member in ascending order of score, or None
+    /// if it isn't in the set. this counts the members ranked before it
+    pub fn zrank<M: AsRef<[u8]>>(&self, member: M) -> Result<Option<u64>> {
+        let member = member.as_ref();
+        let score = match self.members.get(member)? {
+            Some(score) => score,
+            None => return Ok(None),
+        };
+        let mut rank = 0;
+        for key in self.scores.tree.range(..score_key(&score, member)).keys() {
+            key?;
+            rank += 1;
+        }
+        Ok(Some(rank))
+    }
+    /// returns the number of members in the set, which requires scanning them
+    pub fn zcard(&self) -> usize {
+        self.members.len()
+    }
+}
+
+fn score_key(score: &[u8], member: &[u8]) -> Vec<u8> {
+    [score, member].concat()
+}
+
+fn decode_score(score: &[u8]) -> Result<f64> {
+    score.try_into().map(keys::decode_f64).map_err(|_| {
+        SledUtilsError::Deserialize(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "sorted set score isn't an encoded f64",
+        ))
+    })
+}
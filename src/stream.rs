@@ -0,0 +1,42 @@
+//! asynchronous iteration over trees, driving sled's blocking iterators on a
+//! background thread so large scans don't stall the executor
+
+use crate::blocking::{self, Receiver};
+use crate::deserialize_entry;
+use crate::error::Result;
+use borsh::BorshDeserialize;
+use sled::IVec;
+use std::future::poll_fn;
+use std::task::{Context, Poll};
+
+/// the number of entries read ahead of the consumer of a TreeStream
+pub const STREAM_BUFFER: usize = 64;
+
+/// TreeStream yields the deserialized entries of a tree asynchronously. entries
+/// are read by a background thread at most `STREAM_BUFFER` entries ahead, which
+/// stops once the stream is dropped
+pub struct TreeStream<T> {
+    receiver: Receiver<Result<(IVec, T)>>,
+}
+
+impl<T: BorshDeserialize + Send + 'static> TreeStream<T> {
+    pub(crate) fn new(iter: sled::Iter) -> Self {
+        let (sender, receiver) = blocking::channel(STREAM_BUFFER);
+        std::thread::spawn(move || {
+            for entry in iter {
+                if !sender.send(deserialize_entry(entry)) {
+                    break;
+                }
+            }
+        });
+        Self { receiver }
+    }
+    /// polls for the next entry, returning None once every entry was yielded
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<(IVec, T)>>> {
+        self.receiver.poll_recv(cx)
+    }
+    /// resolves to the next entry, or None once every entry was yielded
+    pub async fn next(&mut self) -> Option<Result<(IVec, T)>> {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+}
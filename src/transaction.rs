@@ -0,0 +1,266 @@
+//! typed operations on the trees handed to `Database::transaction`, and the
+//! transactions through which the tree wrappers write
+
+use crate::cdc::CdcRecord;
+use crate::error::{Result, SledUtilsError};
+use crate::ttl::{entry_id, expiration_key, has_passed};
+use crate::types::DbKey;
+use crate::{derive_key, deserialize_value, serialize_value, DbTree};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree,
+};
+use sled::{IVec, Transactional, Tree};
+
+/// TypedTransactionalTree adds borsh typed variants of the operations of a
+/// TransactionalTree. failing to serialize or deserialize a value aborts the
+/// transaction with the error, so these are meant for transactions whose error
+/// type is SledUtilsError, which `?` converts into a SledUtilsError afterwards
+pub trait TypedTransactionalTree {
+    /// inserts value under the key derived from it, returning the previous value if any
+    fn insert_value<T: BorshSerialize + DbKey>(
+        &self,
+        value: &T,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError>;
+    /// deserializes the value stored under key, returning None if the key is absent
+    fn get_deserialize<K: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        key: K,
+    ) -> ConflictableTransactionResult<Option<T>, SledUtilsError>;
+    /// removes the value stored under key, returning it deserialized if any
+    fn remove_deserialize<K: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        key: K,
+    ) -> ConflictableTransactionResult<Option<T>, SledUtilsError>;
+}
+
+impl TypedTransactionalTree for TransactionalTree {
+    fn insert_value<T: BorshSerialize + DbKey>(
+        &self,
+        value: &T,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        let key = derive_key(value).map_err(ConflictableTransactionError::Abort)?;
+        let value = serialize_value(value).map_err(ConflictableTransactionError::Abort)?;
+        Ok(self.insert(key, value)?)
+    }
+    fn get_deserialize<K: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        key: K,
+    ) -> ConflictableTransactionResult<Option<T>, SledUtilsError> {
+        decode(self.get(key)?)
+    }
+    fn remove_deserialize<K: AsRef<[u8]>, T: BorshDeserialize>(
+        &self,
+        key: K,
+    ) -> ConflictableTransactionResult<Option<T>, SledUtilsError> {
+        decode(self.remove(key.as_ref())?)
+    }
+}
+
+/// deserializes a value read within a transaction, aborting it on failure
+pub(crate) fn decode<T: BorshDeserialize>(
+    value: Option<IVec>,
+) -> ConflictableTransactionResult<Option<T>, SledUtilsError> {
+    value
+        .map(|value| deserialize_value(&value))
+        .transpose()
+        .map_err(ConflictableTransactionError::Abort)
+}
+
+/// TxTree is a tree written within `transact`, whose writes are recorded in
+/// the cdc log and replace the deadline of their key in the same transaction
+pub(crate) struct TxTree<'a> {
+    tree: &'a TransactionalTree,
+    /// the name of the tree, by which cdc records and deadlines refer to it
+    name: IVec,
+    cdc: Option<&'a TransactionalTree>,
+    /// the deadlines and expirations trees, if entries of the tree were given a ttl
+    ttl: Option<(&'a TransactionalTree, &'a TransactionalTree)>,
+}
+
+impl TxTree<'_> {
+    /// returns the value stored under key, or None if its deadline has passed
+    pub(crate) fn get<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        let key = key.as_ref();
+        let value = self.tree.get(key)?;
+        if let (Some(_), Some((tx_deadlines, _))) = (&value, self.ttl) {
+            if tx_deadlines
+                .get(entry_id(&self.name, key))?
+                .is_some_and(|deadline| has_passed(&deadline))
+            {
+                return Ok(None);
+            }
+        }
+        Ok(value)
+    }
+    /// inserts value under key, clearing any deadline the key was given, and
+    /// returns the previous value unless it had expired
+    pub(crate) fn insert<K, V>(
+        &self,
+        key: K,
+        value: V,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError>
+    where
+        K: AsRef<[u8]>,
+        V: Into<IVec>,
+    {
+        let previous = self.set_deadline(key.as_ref(), None)?;
+        Ok(unexpired(
+            self.write(key.as_ref(), Some(value.into()))?,
+            previous,
+        ))
+    }
+    /// inserts value under key, expiring it at the big-endian deadline, and
+    /// returns the previous value unless it had expired
+    pub(crate) fn insert_with_deadline<V: Into<IVec>>(
+        &self,
+        key: &[u8],
+        value: V,
+        deadline: [u8; 8],
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        let previous = self.set_deadline(key, Some(deadline))?;
+        Ok(unexpired(self.write(key, Some(value.into()))?, previous))
+    }
+    /// replaces the value under key, keeping any deadline the key was given
+    pub(crate) fn rewrite<V: Into<IVec>>(
+        &self,
+        key: &[u8],
+        value: V,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        self.write(key, Some(value.into()))
+    }
+    /// removes key along with its deadline, returning the previous value unless
+    /// it had expired
+    pub(crate) fn remove<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        let previous = self.set_deadline(key.as_ref(), None)?;
+        Ok(unexpired(self.write(key.as_ref(), None)?, previous))
+    }
+    /// removes key if its deadline is still the big-endian deadline it was
+    /// indexed by, returning true if a value was removed
+    pub(crate) fn expire(
+        &self,
+        key: &[u8],
+        deadline: &[u8],
+    ) -> ConflictableTransactionResult<bool, SledUtilsError> {
+        let (tx_deadlines, tx_expirations) = match self.ttl {
+            Some(ttl) => ttl,
+            None => return Ok(false),
+        };
+        let id = entry_id(&self.name, key);
+        tx_expirations.remove(expiration_key(deadline, &id))?;
+        // the key may have been given a new deadline since it was indexed
+        if tx_deadlines.get(&id)?.as_deref() != Some(deadline) {
+            return Ok(false);
+        }
+        tx_deadlines.remove(id)?;
+        Ok(self.write(key, None)?.is_some())
+    }
+    /// writes key, recording the change in the cdc log if enabled
+    fn write(
+        &self,
+        key: &[u8],
+        value: Option<IVec>,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        if let Some(tx_cdc) = self.cdc {
+            let record = CdcRecord::new(&self.name, key, value.as_deref());
+            let record = serialize_value(&record).map_err(ConflictableTransactionError::Abort)?;
+            tx_cdc.insert(&tx_cdc.generate_id()?.to_be_bytes(), record)?;
+        }
+        Ok(match value {
+            Some(value) => self.tree.insert(key, value)?,
+            None => self.tree.remove(key)?,
+        })
+    }
+    /// replaces the deadline of key, removing it when deadline is None, and
+    /// returns the previous deadline
+    fn set_deadline(
+        &self,
+        key: &[u8],
+        deadline: Option<[u8; 8]>,
+    ) -> ConflictableTransactionResult<Option<IVec>, SledUtilsError> {
+        let (tx_deadlines, tx_expirations) = match self.ttl {
+            Some(ttl) => ttl,
+            None => return Ok(None),
+        };
+        let id = entry_id(&self.name, key);
+        let previous = match deadline {
+            Some(deadline) => tx_deadlines.insert(id.as_slice(), &deadline)?,
+            // reading first avoids writing to the deadlines of keys without one
+            None => match tx_deadlines.get(&id)? {
+                Some(_) => tx_deadlines.remove(id.as_slice())?,
+                None => None,
+            },
+        };
+        if let Some(previous) = &previous {
+            tx_expirations.remove(expiration_key(previous, &id))?;
+        }
+        if let Some(deadline) = deadline {
+            tx_expirations.insert(expiration_key(&deadline, &id), &[])?;
+        }
+        Ok(previous)
+    }
+}
+
+/// returns value unless its deadline has passed, when it's treated as absent
+fn unexpired(value: Option<IVec>, deadline: Option<IVec>) -> Option<IVec> {
+    value.filter(|_| !deadline.is_some_and(|deadline| has_passed(&deadline)))
+}
+
+/// runs f as a single transaction over trees, along with the cdc log and the
+/// deadlines when in use, handing f a TxTree for each tree in the same order.
+/// f may be invoked more than once if a concurrent transaction conflicts
+pub(crate) fn transact<A, F>(trees: &[&DbTree], f: F) -> Result<A>
+where
+    F: Fn(&[TxTree<'_>]) -> ConflictableTransactionResult<A, SledUtilsError>,
+{
+    let cdc = trees.iter().find_map(|tree| tree.cdc.clone());
+    // only the trees which may hold deadlines read and clear them
+    let holders = trees
+        .iter()
+        .map(|tree| tree.deadlines.holds(&tree.tree.name()))
+        .collect::<Vec<_>>();
+    let ttl = trees
+        .iter()
+        .find_map(|tree| tree.deadlines.trees_of(&tree.tree.name()));
+    let names = trees
+        .iter()
+        .map(|tree| tree.tree.name())
+        .collect::<Vec<_>>();
+    let mut all = trees
+        .iter()
+        .map(|tree| tree.tree.clone())
+        .collect::<Vec<Tree>>();
+    all.extend(cdc);
+    if let Some((deadlines, expirations)) = ttl {
+        all.push(deadlines);
+        all.push(expirations);
+    }
+    all.as_slice()
+        .transaction(|tx_all| {
+            let (tx_trees, rest) = tx_all.split_at(names.len());
+            let (cdc, ttl) = match rest {
+                [cdc, deadlines, expirations] => (Some(cdc), Some((deadlines, expirations))),
+                [deadlines, expirations] => (None, Some((deadlines, expirations))),
+                [cdc] => (Some(cdc), None),
+                _ => (None, None),
+            };
+            let tx_trees = tx_trees
+                .iter()
+                .zip(names.iter().zip(&holders))
+                .map(|(tree, (name, holds))| TxTree {
+                    tree,
+                    name: name.clone(),
+                    cdc,
+                    ttl: ttl.filter(|_| *holds),
+                })
+                .collect::<Vec<_>>();
+            f(&tx_trees)
+        })
+        .map_err(SledUtilsError::from)
+}
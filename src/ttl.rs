@@ -0,0 +1,290 @@
+//! expiration of entries inserted with a time to live.
+//!
+//! deadlines are kept outside of the trees they apply to, so values keep their
+//! usual encoding. `DEADLINES_TREE_ID` maps every expiring entry to its deadline,
+//! while `EXPIRATIONS_TREE_ID` indexes the same entries by deadline so purging
+//! only visits entries which are due. an entry is identified by the big-endian
+//! u32 length of its tree's name, followed by the name and then the key
+
+use crate::error::Result;
+use crate::transaction::transact;
+use crate::{Database, DbTree};
+use sled::{IVec, Tree};
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// the tree mapping every expiring entry to its big-endian deadline
+pub const DEADLINES_TREE_ID: &str = "__sled_utils_deadlines";
+/// the tree indexing every expiring entry by its big-endian deadline
+pub const EXPIRATIONS_TREE_ID: &str = "__sled_utils_expirations";
+
+/// Deadlines is a lazily opened handle to the `DEADLINES_TREE_ID` and
+/// `EXPIRATIONS_TREE_ID` trees, shared by a database and the trees opened from
+/// it, which also remembers the trees whose entries were given deadlines. only
+/// those trees consult the deadlines on reads and clear them on writes, so
+/// trees which never use ttls don't pay for them
+#[derive(Clone, Default)]
+pub(crate) struct Deadlines(Arc<RwLock<Option<Trees>>>);
+
+struct Trees {
+    deadlines: Tree,
+    expirations: Tree,
+    /// the names of the trees which any deadline was given to since they were
+    /// loaded, a superset of the trees currently holding deadlines
+    holders: HashSet<IVec>,
+}
+
+impl Deadlines {
+    /// returns a handle which is already open if the deadlines tree exists in
+    /// db, finding the trees holding deadlines with a lookup per tree
+    pub(crate) fn load(db: &sled::Db) -> Result<Self> {
+        let handle = Self::default();
+        if db
+            .tree_names()
+            .iter()
+            .any(|name| name == DEADLINES_TREE_ID.as_bytes())
+        {
+            let deadlines = db.open_tree(DEADLINES_TREE_ID)?;
+            let mut holders = HashSet::new();
+            for name in db.tree_names() {
+                if deadlines.scan_prefix(entry_id(&name, &[])).next().is_some() {
+                    holders.insert(name);
+                }
+            }
+            *handle.0.write().unwrap() = Some(Trees {
+                deadlines,
+                expirations: db.open_tree(EXPIRATIONS_TREE_ID)?,
+                holders,
+            });
+        }
+        Ok(handle)
+    }
+    /// returns the deadlines and expirations trees, creating them if missing,
+    /// after recording that the tree name may hold deadlines from now on
+    pub(crate) fn open_for(&self, db: &sled::Db, name: IVec) -> Result<(Tree, Tree)> {
+        if let Some(trees) = &mut *self.0.write().unwrap() {
+            trees.holders.insert(name);
+            return Ok((trees.deadlines.clone(), trees.expirations.clone()));
+        }
+        let trees = Trees {
+            deadlines: db.open_tree(DEADLINES_TREE_ID)?,
+            expirations: db.open_tree(EXPIRATIONS_TREE_ID)?,
+            holders: HashSet::new(),
+        };
+        let mut guard = self.0.write().unwrap();
+        let trees = guard.get_or_insert(trees);
+        trees.holders.insert(name);
+        Ok((trees.deadlines.clone(), trees.expirations.clone()))
+    }
+    /// returns the deadlines and expirations trees if they were opened
+    pub(crate) fn trees(&self) -> Option<(Tree, Tree)> {
+        self.0
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|trees| (trees.deadlines.clone(), trees.expirations.clone()))
+    }
+    /// returns the deadlines and expirations trees if the tree name may hold
+    /// deadlines
+    pub(crate) fn trees_of(&self, name: &[u8]) -> Option<(Tree, Tree)> {
+        self.0
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|trees| trees.holders.contains(name))
+            .map(|trees| (trees.deadlines.clone(), trees.expirations.clone()))
+    }
+    /// returns true if the tree name may hold deadlines
+    pub(crate) fn holds(&self, name: &[u8]) -> bool {
+        self.0
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|trees| trees.holders.contains(name))
+    }
+    /// returns true if any of keys in tree was given a deadline, which requires
+    /// a lookup per key
+    pub(crate) fn holds_any_of<'a>(
+        &self,
+        tree: &Tree,
+        keys: impl Iterator<Item = &'a IVec>,
+    ) -> sled::Result<bool> {
+        let name = tree.name();
+        if let Some((deadlines, _)) = self.trees_of(&name) {
+            for key in keys {
+                if deadlines.contains_key(entry_id(&name, key))? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+    /// returns true if any key in tree was given a deadline
+    pub(crate) fn holds_any(&self, tree: &Tree) -> sled::Result<bool> {
+        let name = tree.name();
+        match self.trees_of(&name) {
+            Some((deadlines, _)) => Ok(deadlines
+                .scan_prefix(entry_id(&name, &[]))
+                .next()
+                .transpose()?
+                .is_some()),
+            None => Ok(false),
+        }
+    }
+    /// forgets that the tree name may hold deadlines, once it was dropped
+    pub(crate) fn release(&self, name: &[u8]) {
+        if let Some(trees) = &mut *self.0.write().unwrap() {
+            trees.holders.remove(name);
+        }
+    }
+    /// forgets the trees, which must be reopened once they were dropped
+    pub(crate) fn reset(&self) {
+        *self.0.write().unwrap() = None;
+    }
+    /// returns true if key in tree was given a deadline which has passed
+    pub(crate) fn is_expired(&self, tree: &Tree, key: &[u8]) -> sled::Result<bool> {
+        let name = tree.name();
+        let deadline = match self.trees_of(&name) {
+            Some((deadlines, _)) => deadlines.get(entry_id(&name, key))?,
+            None => return Ok(false),
+        };
+        Ok(deadline.is_some_and(|deadline| has_passed(&deadline)))
+    }
+}
+
+/// returns true if name is one of the trees holding deadlines, whose own writes
+/// can't be given deadlines
+pub(crate) fn is_ttl_tree(name: &[u8]) -> bool {
+    name == DEADLINES_TREE_ID.as_bytes() || name == EXPIRATIONS_TREE_ID.as_bytes()
+}
+
+/// returns the milliseconds elapsed since the unix epoch
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// returns the big-endian deadline at which an entry inserted now with ttl expires
+pub(crate) fn deadline_after(ttl: Duration) -> [u8; 8] {
+    now_ms()
+        .saturating_add(ttl.as_millis().min(u64::MAX as u128) as u64)
+        .to_be_bytes()
+}
+
+/// returns true if the big-endian deadline has passed
+pub(crate) fn has_passed(deadline: &[u8]) -> bool {
+    deadline
+        .try_into()
+        .is_ok_and(|deadline| u64::from_be_bytes(deadline) <= now_ms())
+}
+
+pub(crate) fn entry_id(tree: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut id = Vec::with_capacity(4 + tree.len() + key.len());
+    id.extend_from_slice(&(tree.len() as u32).to_be_bytes());
+    id.extend_from_slice(tree);
+    id.extend_from_slice(key);
+    id
+}
+
+fn parse_entry_id(id: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(id.get(..4)?.try_into().ok()?) as usize;
+    let rest = &id[4..];
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+pub(crate) fn expiration_key(deadline: &[u8], id: &[u8]) -> Vec<u8> {
+    [deadline, id].concat()
+}
+
+/// removes every entry whose deadline has passed, returning the number of
+/// entries removed from their trees
+pub(crate) fn purge(db: &Database) -> Result<u64> {
+    let (_, expirations) = match db.deadlines.trees() {
+        Some(trees) => trees,
+        None => return Ok(0),
+    };
+    let due = (now_ms().saturating_add(1)).to_be_bytes();
+    let mut trees: HashMap<Vec<u8>, DbTree> = HashMap::new();
+    let mut count = 0;
+    for expiration in expirations
+        .range((Bound::Unbounded, Bound::Excluded(due)))
+        .keys()
+    {
+        let expiration = expiration?;
+        let parsed = (expiration.len() >= 8)
+            .then(|| expiration.split_at(8))
+            .and_then(|(deadline, id)| Some((deadline, parse_entry_id(id)?)))
+            .and_then(|(deadline, (name, key))| {
+                Some((deadline, std::str::from_utf8(name).ok()?, key))
+            });
+        let (deadline, name, key) = match parsed {
+            Some(parsed) => parsed,
+            // not written by this module, drop it from the index
+            None => {
+                expirations.remove(&expiration)?;
+                continue;
+            }
+        };
+        let tree = match trees.get(name.as_bytes()) {
+            Some(tree) => tree,
+            None => {
+                let tree = db.open_named(name)?;
+                trees.entry(name.as_bytes().to_vec()).or_insert(tree)
+            }
+        };
+        if transact(&[tree], |tx_trees| tx_trees[0].expire(key, deadline))? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// ExpirationWorker periodically purges the expired entries of a database on a
+/// background thread, which is stopped and joined once the worker is dropped
+pub struct ExpirationWorker {
+    /// set to true, and notified, to stop the thread
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ExpirationWorker {
+    pub(crate) fn spawn<F>(db: Arc<Database>, interval: Duration, mut on_purge: F) -> Self
+    where
+        F: FnMut(Result<u64>) + Send + 'static,
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stop.clone();
+        let thread = std::thread::spawn(move || loop {
+            let (stopped, wake) = &*signal;
+            let (stopped, _) = wake
+                .wait_timeout_while(stopped.lock().unwrap(), interval, |stopped| !*stopped)
+                .unwrap();
+            if *stopped {
+                return;
+            }
+            drop(stopped);
+            on_purge(db.purge_expired());
+        });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for ExpirationWorker {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // a panicking callback has already been reported by the thread
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! trees whose keys and values are fixed to a single type
+
+use crate::codec::{BorshCodec, Codec};
+use crate::error::Result;
+use crate::DbTree;
+use sled::IVec;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// TypedTree wraps a DbTree whose values are all of type V, stored under keys
+/// of type K, so that values can't accidentally be read back as another type.
+/// values are serialized with the codec C, borsh unless specified otherwise
+pub struct TypedTree<K, V, C = BorshCodec> {
+    tree: Arc<DbTree>,
+    _types: PhantomData<fn() -> (K, V)>,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<K, V, C> Clone for TypedTree<K, V, C> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            _types: PhantomData,
+            _codec: PhantomData,
+        }
+    }
+}
+
+impl<K: AsRef<[u8]>, V, C: Codec<V>> TypedTree<K, V, C> {
+    pub fn new(tree: Arc<DbTree>) -> Self {
+        Self {
+            tree,
+            _types: PhantomData,
+            _codec: PhantomData,
+        }
+    }
+    /// returns the value stored under key, or None if the key is absent
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.tree
+            .get(key)?
+            .map(|value| C::decode(&value))
+            .transpose()
+    }
+    /// inserts value under key, returning the previous value if any
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>> {
+        self.tree.ensure_writable()?;
+        self.tree
+            .write(key.as_ref().into(), Some(C::encode(value)?.into()))?
+            .map(|previous| C::decode(&previous))
+            .transpose()
+    }
+    /// removes the value stored under key, returning it if any
+    pub fn remove(&self, key: &K) -> Result<Option<V>> {
+        self.tree
+            .remove(key)?
+            .map(|previous| C::decode(&previous))
+            .transpose()
+    }
+    /// returns an iterator which lazily deserializes each value in the tree
+    pub fn iter(&self) -> impl Iterator<Item = Result<(IVec, V)>> {
+        self.tree.iter_deserialize_with::<C, V>()
+    }
+    /// returns the untyped tree
+    pub fn inner(&self) -> &Arc<DbTree> {
+        &self.tree
+    }
+}
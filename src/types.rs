@@ -1,29 +1,529 @@
+use crate::error::{Result, SledUtilsError};
+use std::borrow::Cow;
+
 /// the default tree identifier
 pub const DEFAULT_TREE_ID: &str = "__sled__default";
 
+#[cfg(feature = "derive")]
+pub use sled_utils_derive::DbKey;
+
 pub trait DbKey {
-    /// returns the key of value being inserted into the db
+    /// returns the key of value being inserted into the db, which may be
+    /// built dynamically, for example with `CompositeKey`
     fn key(&self) -> anyhow::Result<Vec<u8>>;
 }
 
-/// various trees and their keys for use with sled
+/// CompositeKey builds a single key out of multiple segments. integers are
+/// encoded big-endian so that keys sharing leading segments sort numerically
+/// by their integer components. variable length byte segments should either be
+/// the last segment or be followed by `separator`, otherwise a longer segment
+/// can sort between the keys of a shorter one
+///
+/// ```
+/// use tulip_sled_util::types::{CompositeKey, DbKey};
+///
+/// struct Session {
+///     user_id: u64,
+///     session_id: u32,
+/// }
+///
+/// impl DbKey for Session {
+///     fn key(&self) -> anyhow::Result<Vec<u8>> {
+///         Ok(CompositeKey::new()
+///             .bytes("session")
+///             .separator()
+///             .u64(self.user_id)
+///             .u32(self.session_id)
+///             .into_bytes())
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CompositeKey {
+    buf: Vec<u8>,
+}
+
+impl CompositeKey {
+    /// the byte appended by `separator`
+    pub const SEPARATOR: u8 = b':';
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// appends a raw byte segment
+    pub fn bytes<B: AsRef<[u8]>>(mut self, segment: B) -> Self {
+        self.buf.extend_from_slice(segment.as_ref());
+        self
+    }
+    /// appends the separator byte
+    pub fn separator(mut self) -> Self {
+        self.buf.push(Self::SEPARATOR);
+        self
+    }
+    /// appends a big-endian u16
+    pub fn u16(self, value: u16) -> Self {
+        self.bytes(value.to_be_bytes())
+    }
+    /// appends a big-endian u32
+    pub fn u32(self, value: u32) -> Self {
+        self.bytes(value.to_be_bytes())
+    }
+    /// appends a big-endian u64
+    pub fn u64(self, value: u64) -> Self {
+        self.bytes(value.to_be_bytes())
+    }
+    /// appends a big-endian u128
+    pub fn u128(self, value: u128) -> Self {
+        self.bytes(value.to_be_bytes())
+    }
+    /// returns the built key
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// KeySegment appends a value to a `CompositeKey`, and is implemented by the
+/// types which can be used as fields of a `#[derive(DbKey)]` key
+pub trait KeySegment {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey;
+}
+
+impl KeySegment for str {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.bytes(self)
+    }
+}
+
+impl KeySegment for String {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.bytes(self)
+    }
+}
+
+impl KeySegment for [u8] {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.bytes(self)
+    }
+}
+
+impl KeySegment for Vec<u8> {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.bytes(self)
+    }
+}
+
+impl KeySegment for u8 {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.bytes([*self])
+    }
+}
+
+impl KeySegment for u16 {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.u16(*self)
+    }
+}
+
+impl KeySegment for u32 {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.u32(*self)
+    }
+}
+
+impl KeySegment for u64 {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.u64(*self)
+    }
+}
+
+impl KeySegment for u128 {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        key.u128(*self)
+    }
+}
+
+impl<T: KeySegment + ?Sized> KeySegment for &T {
+    fn append_to(&self, key: CompositeKey) -> CompositeKey {
+        (**self).append_to(key)
+    }
+}
+
+impl AsRef<[u8]> for CompositeKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl From<CompositeKey> for Vec<u8> {
+    fn from(key: CompositeKey) -> Self {
+        key.buf
+    }
+}
+
+/// KeyBuilder builds a key out of typed segments which can be parsed back by a
+/// KeyReader. unlike `CompositeKey`, variable length segments are terminated,
+/// with every 0x00 byte within them escaped as 0x00 0xff and the terminator
+/// being 0x00 0x01, so a segment never runs into the next one and keys sort by
+/// each of their segments in turn, whatever the lengths of earlier segments
+///
+/// ```
+/// use tulip_sled_util::types::{KeyBuilder, KeyReader};
+///
+/// let key = KeyBuilder::new().str("vault").u64(7).fixed(&[1; 32]).into_bytes();
+/// let mut reader = KeyReader::new(&key);
+/// assert_eq!(reader.str().unwrap(), "vault");
+/// assert_eq!(reader.u64().unwrap(), 7);
+/// assert_eq!(reader.fixed::<32>().unwrap(), [1; 32]);
+/// assert!(reader.finish().is_ok());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeyBuilder {
+    buf: Vec<u8>,
+}
+
+impl KeyBuilder {
+    const ESCAPE: u8 = 0x00;
+    const ESCAPED: u8 = 0xff;
+    const TERMINATOR: u8 = 0x01;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// appends a terminated variable length byte segment
+    pub fn bytes<B: AsRef<[u8]>>(mut self, segment: B) -> Self {
+        for byte in segment.as_ref() {
+            self.buf.push(*byte);
+            if *byte == Self::ESCAPE {
+                self.buf.push(Self::ESCAPED);
+            }
+        }
+        self.buf
+            .extend_from_slice(&[Self::ESCAPE, Self::TERMINATOR]);
+        self
+    }
+    /// appends a terminated utf-8 string segment
+    pub fn str(self, segment: &str) -> Self {
+        self.bytes(segment)
+    }
+    /// appends a big-endian u64
+    pub fn u64(mut self, value: u64) -> Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+    /// appends a fixed width segment as is, such as a 32 byte pubkey
+    pub fn fixed<const N: usize>(mut self, segment: &[u8; N]) -> Self {
+        self.buf.extend_from_slice(segment);
+        self
+    }
+    /// returns the built key
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl AsRef<[u8]> for KeyBuilder {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl From<KeyBuilder> for Vec<u8> {
+    fn from(key: KeyBuilder) -> Self {
+        key.buf
+    }
+}
+
+/// KeyReader parses the segments of a key built by a KeyBuilder, which must be
+/// read in the order and with the types they were appended with
+#[derive(Clone, Debug)]
+pub struct KeyReader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> KeyReader<'a> {
+    pub fn new(key: &'a [u8]) -> Self {
+        Self { rest: key }
+    }
+    /// reads a segment appended through `KeyBuilder::bytes`
+    pub fn bytes(&mut self) -> Result<Vec<u8>> {
+        let mut segment = Vec::new();
+        let mut bytes = self.rest.iter();
+        while let Some(byte) = bytes.next() {
+            if *byte != KeyBuilder::ESCAPE {
+                segment.push(*byte);
+                continue;
+            }
+            match bytes.next() {
+                Some(&KeyBuilder::ESCAPED) => segment.push(KeyBuilder::ESCAPE),
+                Some(&KeyBuilder::TERMINATOR) => {
+                    self.rest = bytes.as_slice();
+                    return Ok(segment);
+                }
+                _ => return Err(invalid_key("invalid escape in key segment")),
+            }
+        }
+        Err(invalid_key("unterminated key segment"))
+    }
+    /// reads a segment appended through `KeyBuilder::str`
+    pub fn str(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid_key("key segment isn't valid utf-8"))
+    }
+    /// reads a segment appended through `KeyBuilder::u64`
+    pub fn u64(&mut self) -> Result<u64> {
+        self.fixed().map(u64::from_be_bytes)
+    }
+    /// reads a segment appended through `KeyBuilder::fixed`
+    pub fn fixed<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.rest.len() < N {
+            return Err(invalid_key("key is shorter than its segments"));
+        }
+        let (segment, rest) = self.rest.split_at(N);
+        self.rest = rest;
+        Ok(segment.try_into().expect("split at N"))
+    }
+    /// returns the bytes which haven't been read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.rest
+    }
+    /// returns an error if any bytes of the key haven't been read
+    pub fn finish(self) -> Result<()> {
+        if self.rest.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_key("key is longer than its segments"))
+        }
+    }
+}
+
+fn invalid_key(msg: &str) -> SledUtilsError {
+    SledUtilsError::DeserializeKey(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+/// TimeKey is a key made of a big-endian millisecond timestamp followed by a
+/// suffix, so entries of time series trees are ordered by time and can be
+/// queried with `DbTree::range_by_time`. the suffix distinguishes entries
+/// sharing a timestamp
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeKey {
+    /// milliseconds since the unix epoch
+    pub timestamp_ms: u64,
+    pub suffix: Vec<u8>,
+}
+
+impl TimeKey {
+    pub fn new<S: AsRef<[u8]>>(timestamp_ms: u64, suffix: S) -> Self {
+        Self {
+            timestamp_ms,
+            suffix: suffix.as_ref().to_vec(),
+        }
+    }
+    /// returns a key timestamped with the current time
+    pub fn now<S: AsRef<[u8]>>(suffix: S) -> Self {
+        Self::new(crate::ttl::now_ms(), suffix)
+    }
+    /// parses a key built by `to_bytes`
+    pub fn parse(key: &[u8]) -> Result<Self> {
+        let mut reader = KeyReader::new(key);
+        let timestamp_ms = reader.u64()?;
+        Ok(Self::new(timestamp_ms, reader.remaining()))
+    }
+    /// returns the timestamp followed by the suffix
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.timestamp_ms.to_be_bytes()[..], &self.suffix].concat()
+    }
+}
+
+impl From<TimeKey> for Vec<u8> {
+    fn from(key: TimeKey) -> Self {
+        key.to_bytes()
+    }
+}
+
+/// GeneratedKey is a 128 bit UUIDv7 generated for values without a natural
+/// key, stored as its 16 big-endian bytes. it starts with the millisecond
+/// timestamp at which it was generated, and keys generated by a process are
+/// strictly increasing, so entries sort by insertion time. as a UUIDv7 is also
+/// a valid ULID, it can be rendered in either format
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GeneratedKey(u128);
+
+impl GeneratedKey {
+    /// generates a new key, whose random bits come from std's randomly seeded hasher
+    pub fn generate() -> Self {
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+        use std::sync::Mutex;
+
+        static LAST: Mutex<u128> = Mutex::new(0);
+        let random = || {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(crate::ttl::now_ms() as u128);
+            hasher.finish() as u128
+        };
+        // the top bit of rand_b is left clear, leaving room to increment within a millisecond
+        let candidate = (crate::ttl::now_ms() as u128 & 0xffff_ffff_ffff) << 80
+            | 0x7 << 76
+            | (random() & 0xfff) << 64
+            | 0b10 << 62
+            | random() & 0x1fff_ffff_ffff_ffff;
+        let mut last = LAST.lock().unwrap();
+        *last = candidate.max(*last + 1);
+        GeneratedKey(*last)
+    }
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        GeneratedKey(u128::from_be_bytes(bytes))
+    }
+    /// returns the key as stored in the tree
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+    /// returns the milliseconds since the unix epoch at which the key was generated
+    pub fn timestamp_ms(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+    /// returns the key as a hyphenated lowercase uuid
+    pub fn to_uuid_string(&self) -> String {
+        let hex = format!("{:032x}", self.0);
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+    /// returns the key as a 26 character crockford base32 ulid
+    pub fn to_ulid_string(&self) -> String {
+        const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+        (0..26)
+            .rev()
+            .map(|digit| ALPHABET[(self.0 >> (digit * 5)) as usize & 0x1f] as char)
+            .collect()
+    }
+}
+
+impl std::fmt::Display for GeneratedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_uuid_string())
+    }
+}
+
+/// fixed width encodings of numbers whose lexicographic byte order matches
+/// their numeric order, so they can be used as keys of range scans
+pub mod keys {
+    const SIGN_BIT: u64 = 1 << 63;
+
+    /// encodes value big-endian
+    pub fn encode_u64(value: u64) -> [u8; 8] {
+        value.to_be_bytes()
+    }
+    pub fn decode_u64(bytes: [u8; 8]) -> u64 {
+        u64::from_be_bytes(bytes)
+    }
+    /// encodes value big-endian with its sign bit flipped, so negative values sort first
+    pub fn encode_i64(value: i64) -> [u8; 8] {
+        (value as u64 ^ SIGN_BIT).to_be_bytes()
+    }
+    pub fn decode_i64(bytes: [u8; 8]) -> i64 {
+        (u64::from_be_bytes(bytes) ^ SIGN_BIT) as i64
+    }
+    /// encodes the bits of value big-endian, flipping the sign bit of positive
+    /// values and every bit of negative ones. -0.0 sorts before 0.0, and NaNs
+    /// sort after positive infinity or before negative infinity depending on their sign
+    pub fn encode_f64(value: f64) -> [u8; 8] {
+        let bits = value.to_bits();
+        let bits = if bits & SIGN_BIT == 0 {
+            bits ^ SIGN_BIT
+        } else {
+            !bits
+        };
+        bits.to_be_bytes()
+    }
+    pub fn decode_f64(bytes: [u8; 8]) -> f64 {
+        let bits = u64::from_be_bytes(bytes);
+        let bits = if bits & SIGN_BIT != 0 {
+            bits ^ SIGN_BIT
+        } else {
+            !bits
+        };
+        f64::from_bits(bits)
+    }
+}
+
+/// various trees and their keys for use with sled. the built-in and namespaced
+/// trees are stored under names starting with `RESERVED_PREFIX`, which a
+/// `Custom` name can't alias, see `DbTrees::str`
 #[derive(Debug, Clone, Copy)]
 pub enum DbTrees<'a> {
     Custom(&'a str),
     Default,
+    /// tree for storing metadata
+    Metadata,
+    /// tree for storing indices
+    Index,
+    /// tree for storing data
+    Data,
+    /// a tree scoped to a namespace, so that modules using the same leaf
+    /// name don't collide. the tree name is `RESERVED_PREFIX` and the byte
+    /// length of `ns`, followed by `ns` and `name`, each preceded by `SEPARATOR`.
+    /// for example `a` and `users` become `__sled_utils_tree:1:a:users`.
+    /// the length prefix keeps namespace
+    /// boundaries unambiguous when either part contains the separator, and
+    /// the parts can be recovered with `DbTrees::parse`
+    Namespaced {
+        ns: &'a str,
+        name: &'a str,
+    },
 }
 
-impl<'a> ToString for DbTrees<'a> {
-    fn to_string(&self) -> String {
-        self.str().to_string()
+/// the separator between the namespace and name of a `DbTrees::Namespaced` tree
+pub const SEPARATOR: &str = ":";
+
+/// the prefix of the names of the built-in and namespaced trees
+pub const RESERVED_PREFIX: &str = "__sled_utils_tree:";
+
+impl<'a> std::fmt::Display for DbTrees<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.str())
     }
 }
 
 impl<'a> DbTrees<'a> {
-    pub fn str(&self) -> &str {
+    /// returns the name of the tree, which is only allocated for namespaced
+    /// trees and escaped custom trees. a `Custom` name starting with
+    /// `RESERVED_PREFIX` is escaped by prefixing it once more, so it never
+    /// opens a built-in or namespaced tree
+    pub fn str(&self) -> Cow<'a, str> {
         match self {
-            DbTrees::Custom(tree_key) => tree_key,
-            DbTrees::Default => DEFAULT_TREE_ID,
+            DbTrees::Custom(tree_key) if tree_key.starts_with(RESERVED_PREFIX) => {
+                Cow::Owned(format!("{}{}", RESERVED_PREFIX, tree_key))
+            }
+            DbTrees::Custom(tree_key) => Cow::Borrowed(tree_key),
+            DbTrees::Default => Cow::Borrowed(DEFAULT_TREE_ID),
+            DbTrees::Metadata => Cow::Borrowed("__sled_utils_tree:metadata"),
+            DbTrees::Index => Cow::Borrowed("__sled_utils_tree:index"),
+            DbTrees::Data => Cow::Borrowed("__sled_utils_tree:data"),
+            DbTrees::Namespaced { ns, name } => Cow::Owned(format!(
+                "{}{}{sep}{}{sep}{}",
+                RESERVED_PREFIX,
+                ns.len(),
+                ns,
+                name,
+                sep = SEPARATOR
+            )),
+        }
+    }
+    /// splits the name of a `DbTrees::Namespaced` tree back into its namespace
+    /// and name, returning None if it isn't a namespaced tree name
+    pub fn parse(name: &str) -> Option<(&str, &str)> {
+        let (len, rest) = name.strip_prefix(RESERVED_PREFIX)?.split_once(SEPARATOR)?;
+        if len.is_empty() || !len.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
         }
+        let len: usize = len.parse().ok()?;
+        let ns = rest.get(..len)?;
+        let name = rest.get(len..)?.strip_prefix(SEPARATOR)?;
+        Some((ns, name))
     }
 }
@@ -0,0 +1,113 @@
+//! secondary keys which are unique across the values of a tree, enforced by an
+//! index tree mapping each secondary key to the primary key of its value
+
+use crate::error::{Result, SledUtilsError};
+use crate::transaction::{decode, transact};
+use crate::types::DbKey;
+use crate::{derive_key, serialize_value, DbTree};
+use borsh::{BorshDeserialize, BorshSerialize};
+use sled::transaction::{abort, ConflictableTransactionError};
+use sled::IVec;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// UniqueKey declares the secondary key of a value, which no two values
+/// stored through a UniqueIndex may share
+pub trait UniqueKey {
+    fn unique_key(&self) -> anyhow::Result<Vec<u8>>;
+}
+
+/// UniqueIndex stores values of type T in a data tree while maintaining an
+/// index tree of their unique keys, updating both in a single transaction.
+/// writes made to the data tree without going through the UniqueIndex aren't indexed
+pub struct UniqueIndex<T> {
+    data: Arc<DbTree>,
+    index: Arc<DbTree>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for UniqueIndex<T> {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            index: self.index.clone(),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: BorshSerialize + BorshDeserialize + DbKey + UniqueKey> UniqueIndex<T> {
+    pub fn new(data: Arc<DbTree>, index: Arc<DbTree>) -> Self {
+        Self {
+            data,
+            index,
+            _value: PhantomData,
+        }
+    }
+    /// inserts value, returning the value previously stored under its key if
+    /// any. fails with `SledUtilsError::DuplicateKey` without writing anything
+    /// if its unique key belongs to a value stored under another key
+    pub fn insert(&self, value: &T) -> Result<Option<T>> {
+        self.data.ensure_writable()?;
+        let key = derive_key(value)?;
+        let unique_key = derive_unique_key(value)?;
+        let value = IVec::from(serialize_value(value)?);
+        transact(&[&self.data, &self.index], |tx_trees| {
+            let (tx_data, tx_index) = (&tx_trees[0], &tx_trees[1]);
+            match tx_index.get(&unique_key)? {
+                Some(owner) if owner != key.as_slice() => {
+                    return abort(SledUtilsError::DuplicateKey(unique_key.as_slice().into()))
+                }
+                _ => (),
+            }
+            let previous = decode::<T>(tx_data.insert(&key, value.clone())?)?;
+            if let Some(previous) = &previous {
+                let previous_key =
+                    derive_unique_key(previous).map_err(ConflictableTransactionError::Abort)?;
+                if previous_key != unique_key {
+                    tx_index.remove(previous_key)?;
+                }
+            }
+            tx_index.insert(&unique_key, key.as_slice())?;
+            Ok(previous)
+        })
+    }
+    /// removes the value stored under key along with its unique key, returning it if any
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<T>> {
+        self.data.ensure_writable()?;
+        let key = key.as_ref();
+        transact(&[&self.data, &self.index], |tx_trees| {
+            let (tx_data, tx_index) = (&tx_trees[0], &tx_trees[1]);
+            let previous = decode::<T>(tx_data.remove(key)?)?;
+            if let Some(previous) = &previous {
+                tx_index.remove(
+                    derive_unique_key(previous).map_err(ConflictableTransactionError::Abort)?,
+                )?;
+            }
+            Ok(previous)
+        })
+    }
+    /// returns the value stored under key, or None if the key is absent
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<T>> {
+        self.data.get_deserialize(key)
+    }
+    /// returns the value whose unique key is unique_key, or None if there is none
+    pub fn get_by_unique_key<K: AsRef<[u8]>>(&self, unique_key: K) -> Result<Option<T>> {
+        match self.index.get(unique_key)? {
+            Some(key) => self.data.get_deserialize(key),
+            None => Ok(None),
+        }
+    }
+    /// returns the tree storing the values
+    pub fn data(&self) -> &Arc<DbTree> {
+        &self.data
+    }
+    /// returns the tree mapping unique keys to the keys of their values
+    pub fn index(&self) -> &Arc<DbTree> {
+        &self.index
+    }
+}
+
+fn derive_unique_key<T: UniqueKey>(value: &T) -> Result<Vec<u8>> {
+    value.unique_key().map_err(SledUtilsError::Key)
+}
@@ -0,0 +1,59 @@
+//! values wrapped in an envelope recording the schema version they were
+//! written with, so records written by older versions of a type stay readable
+
+use crate::codec::Codec;
+use crate::error::{Result, SledUtilsError};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+/// Envelope is the stored form of a versioned value, holding the borsh
+/// serialized payload along with the schema version of the type which wrote it
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub schema_version: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Versioned is implemented by types stored in envelopes. values are always
+/// written with SCHEMA_VERSION, while payloads of older versions are handed to
+/// `decode_version` so they can be converted into the current type
+pub trait Versioned: BorshSerialize + BorshDeserialize {
+    const SCHEMA_VERSION: u32;
+
+    /// decodes a payload written with an earlier schema version. the default
+    /// rejects every earlier version
+    fn decode_version(schema_version: u32, _payload: &[u8]) -> Result<Self> {
+        Err(unsupported_version(schema_version))
+    }
+}
+
+/// VersionedCodec stores values in an Envelope, and is how a tree opts into
+/// versioning, either through the `*_with` methods or as the codec of a TypedTree
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VersionedCodec;
+
+impl<T: Versioned> Codec<T> for VersionedCodec {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        let envelope = Envelope {
+            schema_version: T::SCHEMA_VERSION,
+            payload: borsh::to_vec(value).map_err(SledUtilsError::Serialize)?,
+        };
+        borsh::to_vec(&envelope).map_err(SledUtilsError::Serialize)
+    }
+    fn decode(bytes: &[u8]) -> Result<T> {
+        let envelope = Envelope::try_from_slice(bytes).map_err(SledUtilsError::Deserialize)?;
+        if envelope.schema_version == T::SCHEMA_VERSION {
+            T::try_from_slice(&envelope.payload).map_err(SledUtilsError::Deserialize)
+        } else if envelope.schema_version < T::SCHEMA_VERSION {
+            T::decode_version(envelope.schema_version, &envelope.payload)
+        } else {
+            Err(unsupported_version(envelope.schema_version))
+        }
+    }
+}
+
+fn unsupported_version(schema_version: u32) -> SledUtilsError {
+    SledUtilsError::Deserialize(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("unsupported schema version {}", schema_version),
+    ))
+}
@@ -0,0 +1,105 @@
+//! typed wrappers around sled's key-change subscriptions
+
+use crate::deserialize_value;
+use crate::error::Result;
+use borsh::BorshDeserialize;
+use sled::{IVec, Subscriber};
+use std::marker::PhantomData;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+/// TypedSubscriber wraps a sled::Subscriber, deserializing the value of every
+/// event it receives. each item is `(key, Some(value))` for an insert and
+/// `(key, None)` for a removal, or an error if the inserted value failed
+/// to be deserialized
+pub struct TypedSubscriber<T> {
+    subscriber: Subscriber,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: BorshDeserialize> TypedSubscriber<T> {
+    pub fn new(subscriber: Subscriber) -> Self {
+        Self {
+            subscriber,
+            _value: PhantomData,
+        }
+    }
+    /// blocks until the next event is received or timeout elapses
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> std::result::Result<Result<(IVec, Option<T>)>, RecvTimeoutError> {
+        self.subscriber.next_timeout(timeout).map(deserialize_event)
+    }
+    /// returns the inner subscriber
+    pub fn into_inner(self) -> Subscriber {
+        self.subscriber
+    }
+}
+
+impl<T: BorshDeserialize> Iterator for TypedSubscriber<T> {
+    type Item = Result<(IVec, Option<T>)>;
+
+    /// blocks until the next event is received
+    fn next(&mut self) -> Option<Self::Item> {
+        self.subscriber.next().map(deserialize_event)
+    }
+}
+
+/// Event is a typed change of a watched key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event<T> {
+    /// a value was inserted
+    Insert(T),
+    /// the key was removed
+    Remove(IVec),
+}
+
+impl<T> From<(IVec, Option<T>)> for Event<T> {
+    fn from((key, value): (IVec, Option<T>)) -> Self {
+        match value {
+            Some(value) => Event::Insert(value),
+            None => Event::Remove(key),
+        }
+    }
+}
+
+/// EventSubscriber adapts a TypedSubscriber to yield each change as an `Event`
+pub struct EventSubscriber<T>(TypedSubscriber<T>);
+
+impl<T: BorshDeserialize> EventSubscriber<T> {
+    pub fn new(subscriber: TypedSubscriber<T>) -> Self {
+        Self(subscriber)
+    }
+    /// blocks until the next event is received or timeout elapses
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> std::result::Result<Result<Event<T>>, RecvTimeoutError> {
+        self.0
+            .next_timeout(timeout)
+            .map(|change| change.map(Event::from))
+    }
+    /// returns the inner subscriber
+    pub fn into_inner(self) -> TypedSubscriber<T> {
+        self.0
+    }
+}
+
+impl<T: BorshDeserialize> Iterator for EventSubscriber<T> {
+    type Item = Result<Event<T>>;
+
+    /// blocks until the next event is received
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|change| change.map(Event::from))
+    }
+}
+
+pub(crate) fn deserialize_event<T: BorshDeserialize>(
+    event: sled::Event,
+) -> Result<(IVec, Option<T>)> {
+    match event {
+        sled::Event::Insert { key, value } => Ok((key, Some(deserialize_value(&value)?))),
+        sled::Event::Remove { key } => Ok((key, None)),
+    }
+}